@@ -0,0 +1,206 @@
+use crate::protocol::{self, Handle};
+use crate::soc::Soc;
+use std::time::{Duration, Instant};
+
+// SAR ADC, after Linux drivers/iio/adc/meson_saradc.c
+//
+// We sample one channel at a time through the FIFO. Channel 7 can be muxed to
+// fractions of the reference voltage, which gives a two point calibration.
+
+const REG0: u32 = 0x00;
+const CHAN_LIST: u32 = 0x04;
+const REG3: u32 = 0x0c;
+const FIFO_RD: u32 = 0x18;
+const AUX_SW: u32 = 0x1c;
+const CHAN_10_SW: u32 = 0x20;
+const REG11: u32 = 0x2c;
+
+const REG0_BUSY_MASK: u32 = 0x7 << 28;
+const REG0_FIFO_COUNT_SHIFT: u32 = 21;
+const REG0_FIFO_COUNT_MASK: u32 = 0x1f << REG0_FIFO_COUNT_SHIFT;
+const REG0_SAMPLING_STOP: u32 = 1 << 14;
+const REG0_SAMPLING_START: u32 = 1 << 2;
+const REG0_CONTINUOUS_EN: u32 = 1 << 1;
+const REG0_SAMPLE_ENGINE_ENABLE: u32 = 1 << 0;
+
+const REG3_CLK_EN: u32 = 1 << 30;
+const REG3_CHAN7_MUX_SHIFT: u32 = 23;
+const REG3_CHAN7_MUX_MASK: u32 = 0x7 << REG3_CHAN7_MUX_SHIFT;
+const REG3_ADC_EN: u32 = 1 << 21;
+const REG3_CLK_DIV_SHIFT: u32 = 10;
+const REG3_CLK_DIV_MASK: u32 = 0x3f << REG3_CLK_DIV_SHIFT;
+
+const REG11_BANDGAP_EN: u32 = 1 << 13;
+
+const FIFO_RD_VALUE_MASK: u32 = 0xfff;
+
+const CHAN0_MUX_SHIFT: u32 = 7;
+const CHAN1_MUX_SHIFT: u32 = 23;
+const AUX_SW_MUX_MASK: u32 = 0x3ffff << 8;
+
+// channel 7 inputs
+const MUX_VDD_DIV4: u32 = 1;
+const MUX_VDD_MUL3_DIV4: u32 = 3;
+const MUX_CH7_INPUT: u32 = 7;
+
+pub const CHANNELS: u8 = 8;
+const VREF_MV: u32 = 1800;
+
+// The ADC clock is the 24 MHz crystal, divided down to 1.2 MHz.
+const CLK_CNTL_GATE: u32 = 1 << 8;
+const CLK_CNTL_DIV_MASK: u32 = 0xff;
+const CLK_CNTL_MUX_MASK: u32 = 0x3 << 9;
+const CLK_DIV: u32 = 19;
+
+const SAMPLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+struct Layout {
+    base: u32,
+    /// HHI_SAR_CLK_CNTL on GX, AO_SAR_CLK on G12
+    clk_cntl: u32,
+    /// peripheral clock gate
+    gate: (u32, u32),
+    bits: u32,
+}
+
+fn layout(soc: Soc) -> Layout {
+    match soc {
+        Soc::Gxbb => Layout {
+            base: 0xc110_8680,
+            clk_cntl: 0xc883_c3d8,
+            gate: (0xc883_c140, 1 << 22),
+            bits: 10,
+        },
+        Soc::Gxl | Soc::Gxm => Layout {
+            base: 0xc110_8680,
+            clk_cntl: 0xc883_c3d8,
+            gate: (0xc883_c140, 1 << 22),
+            bits: 12,
+        },
+        Soc::G12a | Soc::G12b | Soc::Sm1 => Layout {
+            base: 0xff80_9000,
+            clk_cntl: 0xff80_0090,
+            gate: (0xff80_004c, 1 << 8),
+            bits: 12,
+        },
+    }
+}
+
+pub struct Adc<'a> {
+    h: &'a Handle,
+    t: Duration,
+    l: Layout,
+}
+
+impl<'a> Adc<'a> {
+    /// Ungate and set up the clocks, route the channels and power up.
+    pub fn init(h: &'a Handle, t: Duration, soc: Soc) -> Result<Self, String> {
+        let l = layout(soc);
+        let (gate, mask) = l.gate;
+        protocol::modify_reg(h, t, gate, 0, mask, 0)?;
+        let clear = CLK_CNTL_DIV_MASK | CLK_CNTL_MUX_MASK;
+        protocol::modify_reg(h, t, l.clk_cntl, clear, CLK_CNTL_GATE | CLK_DIV, 0)?;
+
+        let a = Adc { h, t, l };
+        // channels 0 and 1 on their inputs, 2 to 7 via the aux switch
+        let ch10 = 1 << CHAN1_MUX_SHIFT;
+        a.modify(
+            CHAN_10_SW,
+            (0x7 << CHAN1_MUX_SHIFT) | (0x7 << CHAN0_MUX_SHIFT),
+            ch10,
+        )?;
+        let aux = (2..8).fold(0, |v, c| v | (c << (8 + (c - 2) * 3)));
+        a.modify(AUX_SW, AUX_SW_MUX_MASK, aux)?;
+
+        a.modify(REG11, 0, REG11_BANDGAP_EN)?;
+        a.modify(
+            REG3,
+            REG3_CLK_DIV_MASK,
+            (20 << REG3_CLK_DIV_SHIFT) | REG3_ADC_EN,
+        )?;
+        a.modify(REG3, 0, REG3_CLK_EN)?;
+        Ok(a)
+    }
+
+    fn modify(&self, reg: u32, clear: u32, set: u32) -> Result<(), String> {
+        protocol::modify_reg(self.h, self.t, self.l.base + reg, clear, set, 0)?;
+        Ok(())
+    }
+
+    fn read(&self, reg: u32) -> Result<u32, String> {
+        protocol::read_reg(self.h, self.t, self.l.base + reg)
+    }
+
+    /// Full scale raw value
+    pub fn max(&self) -> u32 {
+        (1 << self.l.bits) - 1
+    }
+
+    /// Take a single sample of a channel.
+    pub fn sample(&self, channel: u8) -> Result<u32, String> {
+        if channel >= CHANNELS {
+            return Err(format!("No ADC channel {channel}, 0 to 7"));
+        }
+        // drain the FIFO
+        while self.read(REG0)? & REG0_FIFO_COUNT_MASK != 0 {
+            self.read(FIFO_RD)?;
+        }
+        // one entry in the list: our channel
+        protocol::write_reg(self.h, self.t, self.l.base + CHAN_LIST, channel as u32)?;
+        self.modify(
+            REG0,
+            REG0_CONTINUOUS_EN | REG0_SAMPLING_STOP,
+            REG0_SAMPLE_ENGINE_ENABLE,
+        )?;
+        self.modify(REG0, 0, REG0_SAMPLING_START)?;
+        let start = Instant::now();
+        loop {
+            let r0 = self.read(REG0)?;
+            if r0 & REG0_BUSY_MASK == 0 && r0 & REG0_FIFO_COUNT_MASK != 0 {
+                break;
+            }
+            if start.elapsed() > SAMPLE_TIMEOUT {
+                return Err(format!("ADC channel {channel} timed out"));
+            }
+        }
+        let v = self.read(FIFO_RD)? & FIFO_RD_VALUE_MASK;
+        self.modify(REG0, REG0_SAMPLE_ENGINE_ENABLE, REG0_SAMPLING_STOP)?;
+        // GXBB has 10 bits, in the low bits of the 12 bit field
+        Ok(v & self.max())
+    }
+
+    fn sample_ch7(&self, mux: u32) -> Result<u32, String> {
+        self.modify(REG3, REG3_CHAN7_MUX_MASK, mux << REG3_CHAN7_MUX_SHIFT)?;
+        let v = self.sample(7);
+        self.modify(
+            REG3,
+            REG3_CHAN7_MUX_MASK,
+            MUX_CH7_INPUT << REG3_CHAN7_MUX_SHIFT,
+        )?;
+        v
+    }
+
+    /// Measure 1/4 and 3/4 of the reference, returns gain and offset such
+    /// that `ideal = raw * gain + offset`.
+    pub fn calibrate(&self) -> Result<(f64, f64), String> {
+        let lo = self.sample_ch7(MUX_VDD_DIV4)? as f64;
+        let hi = self.sample_ch7(MUX_VDD_MUL3_DIV4)? as f64;
+        if hi <= lo {
+            return Err(format!("Bad calibration samples {lo} and {hi}"));
+        }
+        let scale = (1u32 << self.l.bits) as f64;
+        let gain = (scale / 2.0) / (hi - lo);
+        let offset = scale / 4.0 - lo * gain;
+        Ok((gain, offset))
+    }
+
+    /// Sample a channel, calibrated, in millivolts
+    pub fn read_mv(&self, channel: u8) -> Result<(u32, u32), String> {
+        let (gain, offset) = self.calibrate()?;
+        let raw = self.sample(channel)?;
+        let v = (raw as f64 * gain + offset).clamp(0.0, self.max() as f64);
+        let scale = (1u32 << self.l.bits) as f64;
+        let mv = (v * VREF_MV as f64 / scale).round() as u32;
+        Ok((raw, mv))
+    }
+}
@@ -0,0 +1,195 @@
+use crate::adc::Adc;
+use crate::gpio::Level;
+use crate::protocol::Handle;
+use crate::soc::Soc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Board profiles, see boards/*.toml for the format.
+//
+// The files in boards/ are built in. Profiles in the user's config directory
+// (~/.config/aml_boot/boards/<name>.toml) add boards or replace built-in ones.
+
+const BUILTIN: [(&str, &str); 3] = [
+    ("khadas-vim1", include_str!("../boards/khadas-vim1.toml")),
+    ("lc-a311d-cc", include_str!("../boards/lc-a311d-cc.toml")),
+    ("lc-s905d3-cc", include_str!("../boards/lc-s905d3-cc.toml")),
+];
+
+/// An LED, a button or a strap on a GPIO
+#[derive(Deserialize, Clone, Debug)]
+pub struct Io {
+    pub name: String,
+    pub pin: String,
+    #[serde(default)]
+    pub active_low: bool,
+}
+
+impl Io {
+    /// Pin level for the LED being on or the button being pressed
+    pub fn level(&self, on: bool) -> Level {
+        match on != self.active_low {
+            true => Level::High,
+            false => Level::Low,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Uart {
+    pub name: String,
+    pub tx: String,
+    pub rx: String,
+    pub baud: u32,
+}
+
+/// Hardware revision, told by the voltage of a resistor divider on an ADC
+/// channel
+#[derive(Deserialize, Clone, Debug)]
+pub struct Revision {
+    pub name: String,
+    pub channel: u8,
+    pub min_mv: u32,
+    pub max_mv: u32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Board {
+    pub name: String,
+    pub soc: Soc,
+    /// where to write and run code by default
+    pub load_addr: u32,
+    #[serde(default, rename = "led")]
+    pub leds: Vec<Io>,
+    #[serde(default, rename = "button")]
+    pub buttons: Vec<Io>,
+    /// boot mode and other configuration pins, read only
+    #[serde(default, rename = "strap")]
+    pub straps: Vec<Io>,
+    pub uart: Option<Uart>,
+    #[serde(default, rename = "revision")]
+    pub revisions: Vec<Revision>,
+}
+
+fn user_file(name: &str) -> Option<std::path::PathBuf> {
+    let p = crate::regs::config_dir()?
+        .join("boards")
+        .join(format!("{name}.toml"));
+    p.exists().then_some(p)
+}
+
+fn parse(name: &str, src: &str) -> Result<Board, String> {
+    toml::from_str(src).map_err(|e| format!("{name}: {e}"))
+}
+
+/// Load a board profile by name, e.g. khadas-vim1.
+pub fn load(name: &str) -> Result<Board, String> {
+    if let Some(p) = user_file(name) {
+        let src = std::fs::read_to_string(&p).map_err(|e| format!("{}: {e}", p.display()))?;
+        return parse(&p.to_string_lossy(), &src);
+    }
+    let (_, src) = BUILTIN
+        .iter()
+        .find(|(n, _)| *n == name)
+        .ok_or(format!("Unknown board {name}, see `board list`"))?;
+    parse(name, src)
+}
+
+/// Names of all known boards, built-in and user-defined
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN.iter().map(|(n, _)| n.to_string()).collect();
+    let dir = crate::regs::config_dir().map(|d| d.join("boards"));
+    if let Some(Ok(entries)) = dir.map(std::fs::read_dir) {
+        for e in entries.flatten() {
+            let p = e.path();
+            if p.extension().is_some_and(|x| x == "toml") {
+                if let Some(n) = p.file_stem() {
+                    names.push(n.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+pub fn list() -> Result<(), String> {
+    for n in names() {
+        let b = load(&n)?;
+        println!("  {n:<16} {:<6} {}", b.soc, b.name);
+    }
+    Ok(())
+}
+
+pub fn show(b: &Board) {
+    println!("{} ({})", b.name, b.soc);
+    println!("  load address {:08x}", b.load_addr);
+    let polarity = |low| if low { "active low" } else { "active high" };
+    for l in &b.leds {
+        println!(
+            "  LED    {:<10} {:<10} {}",
+            l.name,
+            l.pin,
+            polarity(l.active_low)
+        );
+    }
+    for k in &b.buttons {
+        println!(
+            "  button {:<10} {:<10} {}",
+            k.name,
+            k.pin,
+            polarity(k.active_low)
+        );
+    }
+    for s in &b.straps {
+        println!("  strap  {:<10} {}", s.name, s.pin);
+    }
+    if let Some(u) = &b.uart {
+        println!(
+            "  UART   {:<10} TX {} RX {} @ {}",
+            u.name, u.tx, u.rx, u.baud
+        );
+    }
+    for r in &b.revisions {
+        let (c, lo, hi) = (r.channel, r.min_mv, r.max_mv);
+        println!("  rev    {:<10} ADC {c} {lo}..{hi} mV", r.name);
+    }
+}
+
+/// Find the board and revision by their ADC voltages, among the profiles for
+/// a SoC.
+pub fn identify(h: &Handle, t: Duration, soc: Soc) -> Result<(Board, Revision), String> {
+    let mut boards = Vec::new();
+    for n in names() {
+        let b = load(&n)?;
+        if b.soc == soc && !b.revisions.is_empty() {
+            boards.push(b);
+        }
+    }
+    if boards.is_empty() {
+        return Err(format!(
+            "No {soc} board profile lists revisions, pass --board instead"
+        ));
+    }
+    let adc = Adc::init(h, t, soc)?;
+    let mut mv: HashMap<u8, u32> = HashMap::new();
+    for b in boards {
+        for r in &b.revisions {
+            let v = match mv.get(&r.channel) {
+                Some(v) => *v,
+                None => {
+                    let (_, v) = adc.read_mv(r.channel)?;
+                    println!("ADC channel {}: {v} mV", r.channel);
+                    mv.insert(r.channel, v);
+                    v
+                }
+            };
+            if (r.min_mv..=r.max_mv).contains(&v) {
+                return Ok((b.clone(), r.clone()));
+            }
+        }
+    }
+    Err(format!("No {soc} board profile matches, see `board show`"))
+}
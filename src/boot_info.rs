@@ -0,0 +1,89 @@
+use crate::board::Board;
+use crate::gpio;
+use crate::protocol::{self, Handle};
+use crate::regs::RegDb;
+use std::time::Duration;
+
+// Why are we in USB mode? The reboot reason survives resets in a sticky AO
+// register, and the ROM notes the device it booted from. Values after Amlogic
+// U-Boot.
+
+const REBOOT_MODES: [&str; 16] = [
+    "cold boot",
+    "normal reboot",
+    "factory reset",
+    "update",
+    "fastboot",
+    "suspend off",
+    "hibernate",
+    "bootloader",
+    "shutdown reboot",
+    "RPMB provisioning",
+    "quiescent",
+    "rescue",
+    "kernel panic",
+    "watchdog reset",
+    "recovery quiescent",
+    "reboot clear",
+];
+
+const BOOT_DEVICES: [&str; 6] = ["none", "eMMC", "NAND", "SPI NOR", "SD card", "USB"];
+
+// The ROM tries these in order unless the POC straps say otherwise, and ends
+// up in USB mode when none of them has a valid boot image.
+const BOOT_ORDER: [&str; 4] = ["SPI NOR", "eMMC", "SD card", "USB"];
+
+fn field(h: &Handle, t: Duration, db: &RegDb, reg: &str, field: &str) -> Result<u32, String> {
+    let r = db.get(reg)?;
+    let f = r
+        .field(field)
+        .ok_or(format!("{reg} has no field {field}"))?;
+    Ok(f.bits.get(protocol::read_reg(h, t, r.addr)?))
+}
+
+pub fn print(h: &Handle, t: Duration, db: &RegDb, board: Option<&Board>) -> Result<(), String> {
+    let mode = field(h, t, db, "AO_SEC_SD_CFG15", "REBOOT_MODE")?;
+    let device = field(h, t, db, "AO_SEC_GP_CFG0", "BOOT_DEVICE")?;
+    let mode_name = REBOOT_MODES.get(mode as usize).unwrap_or(&"unknown");
+    let device_name = BOOT_DEVICES.get(device as usize).unwrap_or(&"unknown");
+
+    println!("Reboot reason:  {mode} ({mode_name})");
+    println!("Boot device:    {device} ({device_name})");
+    if let Some(r) = db.find("ASSIST_POR_CONFIG") {
+        let poc = protocol::read_reg(h, t, r.addr)?;
+        println!("POC straps:     {poc:08x}");
+    }
+
+    println!();
+    println!("Default ROM boot order: {}", BOOT_ORDER.join(", "));
+    match mode {
+        0xd => println!("The last reset was a watchdog reset."),
+        0xc => println!("The last reset followed a kernel panic."),
+        0x7 | 0x3 => println!("Firmware asked for the bootloader or an update before the reset."),
+        _ => {}
+    }
+    // none or USB
+    if matches!(device, 0 | 5) {
+        let tried = &BOOT_ORDER[..BOOT_ORDER.len() - 1];
+        println!("No boot image was found on: {}", tried.join(", "));
+        println!("Those were missing, empty or failed, or straps skipped them.");
+    }
+
+    if let Some(b) = board {
+        println!();
+        for k in &b.buttons {
+            let p = gpio::pin(b.soc, &k.pin)?;
+            let pressed = gpio::get_level(h, t, &p)? == k.level(true);
+            println!(
+                "Button {:<16} {}",
+                k.name,
+                if pressed { "pressed" } else { "released" }
+            );
+        }
+        for s in &b.straps {
+            let p = gpio::pin(b.soc, &s.pin)?;
+            println!("Strap  {:<16} {:?}", s.name, gpio::get_level(h, t, &p)?);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use crate::protocol::{
+    self, Handle, REQ_CHIPINFO, REQ_CHIP_GEN, REQ_IDENTIFY_HOST, REQ_TYPE_AMLIN,
+};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Which requests the ROM implements. Unknown IN requests are not stalled but
+// answered with the REQ_CHIP_GEN data, e.g. REQ_CHIPINFO on the S905X, so a
+// request counts as supported when it is accepted and answers something else.
+// Results are cached per ROM and stage version in ~/.config/aml_boot/caps.json.
+// OUT requests are acknowledged whether known or not, so only IN requests
+// without side effects can be probed. READ_MEM is left out: no address is safe
+// to read on every SoC before we know which one it is.
+
+// (request, wIndex, length)
+#[rustfmt::skip]
+const PROBES: [(u8, u16, usize); 2] = [
+    (REQ_IDENTIFY_HOST, 0, 6),
+    (REQ_CHIPINFO, 0, 64),
+];
+
+type Cache = BTreeMap<String, BTreeMap<String, bool>>;
+
+pub struct Caps {
+    pub rom: String,
+    pub stage: String,
+    pub supported: BTreeMap<String, bool>,
+}
+
+impl Caps {
+    /// Nothing known, every request is tried.
+    pub fn unknown() -> Self {
+        Caps {
+            rom: "?".to_string(),
+            stage: "?".to_string(),
+            supported: BTreeMap::new(),
+        }
+    }
+
+    /// Fail for requests the ROM is known not to implement.
+    pub fn require(&self, req: u8) -> Result<(), String> {
+        let name = protocol::req_name(req).unwrap_or("?");
+        match self.supported.get(name) {
+            Some(false) => Err(format!("{name} is not supported by ROM {}", self.rom)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("  Probed requests (ROM {} stage {}):", self.rom, self.stage);
+        for (name, ok) in &self.supported {
+            println!("    {name:13} {}", if *ok { "yes" } else { "no" });
+        }
+    }
+}
+
+fn cache_path() -> Option<PathBuf> {
+    crate::regs::config_dir().map(|d| d.join("caps.json"))
+}
+
+fn load_cache() -> Cache {
+    cache_path()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_cache(c: &Cache) -> Result<(), String> {
+    let p = cache_path().ok_or("No config directory")?;
+    if let Some(d) = p.parent() {
+        std::fs::create_dir_all(d).map_err(|e| format!("{}: {e}", d.display()))?;
+    }
+    let j = serde_json::to_string_pretty(c).map_err(|e| e.to_string())?;
+    std::fs::write(&p, j).map_err(|e| format!("{}: {e}", p.display()))
+}
+
+/// Probe the requests, `None` if the answer says nothing, e.g. a timeout.
+fn probe(h: &Handle, t: Duration) -> BTreeMap<String, Option<bool>> {
+    let mut res = BTreeMap::new();
+    for (req, index, len) in PROBES {
+        let mut fallback = vec![0; len];
+        let mut buf = vec![0; len];
+        let r = h
+            .read_control(REQ_TYPE_AMLIN, REQ_CHIP_GEN, 0, 0, &mut fallback, t)
+            .and_then(|_| h.read_control(REQ_TYPE_AMLIN, req, 0, index, &mut buf, t))
+            .map(|n| buf[..n] != fallback[..n]);
+        let r = match r {
+            Ok(ok) => Some(ok),
+            Err(rusb::Error::Pipe) => Some(false),
+            Err(_) => None,
+        };
+        res.insert(protocol::req_name(req).unwrap().to_string(), r);
+    }
+    res
+}
+
+/// Get the capabilities of the ROM, probing them unless cached or `fresh`.
+pub fn get(h: &Handle, t: Duration, fresh: bool) -> Result<Caps, String> {
+    let id = protocol::identify_host(h, t)?;
+    let rom = format!("{}.{}", id[0], id[1]);
+    let stage = format!("{}.{}", id[2], id[3]);
+    let key = format!("{rom}/{stage}");
+
+    let mut cache = load_cache();
+    if let (false, Some(s)) = (fresh, cache.get(&key)) {
+        return Ok(Caps {
+            rom,
+            stage,
+            supported: s.clone(),
+        });
+    }
+
+    println!("Probing requests of ROM {rom} stage {stage}");
+    let probed = probe(h, t);
+    let supported: BTreeMap<_, _> = probed
+        .iter()
+        .filter_map(|(n, ok)| ok.map(|ok| (n.clone(), ok)))
+        .collect();
+    // Only cache complete results, a timeout may be a hiccup.
+    if supported.len() == probed.len() {
+        cache.insert(key, supported.clone());
+        if let Err(e) = save_cache(&cache) {
+            println!("Cannot cache capabilities: {e}");
+        }
+    }
+    Ok(Caps {
+        rom,
+        stage,
+        supported,
+    })
+}
+
+/// Like `get`, but warn and go on with unknown capabilities on errors.
+pub fn get_or_unknown(h: &Handle, t: Duration, fresh: bool) -> Caps {
+    get(h, t, fresh).unwrap_or_else(|e| {
+        println!("Cannot get the ROM capabilities, trying anyway: {e}");
+        Caps::unknown()
+    })
+}
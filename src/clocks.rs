@@ -0,0 +1,345 @@
+use crate::protocol::{self, Handle};
+use crate::regs::Bits;
+use crate::soc::Soc;
+use std::time::Duration;
+
+// Clock tree as the ROM left it, after Linux drivers/clk/meson/{gxbb,g12a}.c
+//
+// PLLs run at xtal * (M + frac) / N, divided by 2^OD. The MPLLs divide the
+// fixed PLL by N2 + SDM / 16384.
+//
+// The CPU clock muxes differ by family: GX keeps the Meson8 layout of
+// meson8b.c (xtal or sys_pll, a scaler, a final mux), G12 has two dynamic
+// channels from xtal and fixed dividers. G12B has a second cluster with its
+// own PLL and mux, as Linux models in g12a.c.
+
+const XTAL: f64 = 24_000_000.0;
+const SDM_DEN: f64 = 16384.0;
+
+const GX_HHI: u32 = 0xc883_c000;
+const G12_HHI: u32 = 0xff63_c000;
+
+const fn bits(hi: u8, lo: u8) -> Bits {
+    Bits { hi, lo }
+}
+
+const PLL_LOCK: u32 = 1 << 31;
+
+struct Pll {
+    name: &'static str,
+    cntl: u32,
+    m: Bits,
+    n: Bits,
+    od: Bits,
+    /// register and fraction bits
+    frac: Option<(u32, Bits)>,
+    enable: u32,
+}
+
+struct Mpll {
+    name: &'static str,
+    cntl: u32,
+    sdm: Bits,
+    n2: Bits,
+}
+
+struct Layout {
+    g12: bool,
+    hhi: u32,
+    sys: Pll,
+    fixed: Pll,
+    mplls: &'static [Mpll],
+    /// DDR PLL, outside HHI: block base and PLL
+    ddr: (u32, Pll),
+    /// G12B A73 cluster: its PLL and clock control
+    cpu_b: Option<(Pll, u32)>,
+}
+
+#[rustfmt::skip]
+const GX_MPLLS: [Mpll; 3] = [
+    Mpll { name: "mpll0", cntl: 0x298, sdm: bits(13, 0), n2: bits(24, 16) },
+    Mpll { name: "mpll1", cntl: 0x29c, sdm: bits(13, 0), n2: bits(24, 16) },
+    Mpll { name: "mpll2", cntl: 0x2a0, sdm: bits(13, 0), n2: bits(24, 16) },
+];
+
+#[rustfmt::skip]
+const G12_MPLLS: [Mpll; 4] = [
+    Mpll { name: "mpll0", cntl: 0x27c, sdm: bits(13, 0), n2: bits(28, 20) },
+    Mpll { name: "mpll1", cntl: 0x284, sdm: bits(13, 0), n2: bits(28, 20) },
+    Mpll { name: "mpll2", cntl: 0x28c, sdm: bits(13, 0), n2: bits(28, 20) },
+    Mpll { name: "mpll3", cntl: 0x294, sdm: bits(13, 0), n2: bits(28, 20) },
+];
+
+fn layout(soc: Soc) -> Layout {
+    match soc.is_g12() {
+        false => Layout {
+            g12: false,
+            hhi: GX_HHI,
+            sys: Pll {
+                name: "sys_pll",
+                cntl: 0x300,
+                m: bits(8, 0),
+                n: bits(13, 9),
+                od: bits(17, 16),
+                frac: None,
+                enable: 1 << 30,
+            },
+            fixed: Pll {
+                name: "fixed_pll",
+                cntl: 0x280,
+                m: bits(8, 0),
+                n: bits(13, 9),
+                od: bits(17, 16),
+                frac: Some((0x284, bits(11, 0))),
+                enable: 1 << 30,
+            },
+            mplls: &GX_MPLLS,
+            // AM_DDR_PLL_CNTL0, as set up by BL2
+            ddr: (
+                0xc883_6000,
+                Pll {
+                    name: "ddr_pll",
+                    cntl: 0x00,
+                    m: bits(8, 0),
+                    n: bits(13, 9),
+                    od: bits(17, 16),
+                    frac: None,
+                    enable: 1 << 30,
+                },
+            ),
+            cpu_b: None,
+        },
+        true => Layout {
+            g12: true,
+            hhi: G12_HHI,
+            sys: Pll {
+                name: "sys_pll",
+                cntl: 0x2f4,
+                m: bits(7, 0),
+                n: bits(14, 10),
+                od: bits(18, 16),
+                frac: None,
+                enable: 1 << 28,
+            },
+            fixed: Pll {
+                name: "fixed_pll",
+                cntl: 0x2a0,
+                m: bits(7, 0),
+                n: bits(14, 10),
+                od: bits(17, 16),
+                frac: Some((0x2a4, bits(16, 0))),
+                enable: 1 << 28,
+            },
+            mplls: &G12_MPLLS,
+            // AM_DDR_PLL_CNTL0, as set up by BL2
+            ddr: (
+                0xff63_8c00,
+                Pll {
+                    name: "ddr_pll",
+                    cntl: 0x00,
+                    m: bits(8, 0),
+                    n: bits(14, 10),
+                    od: bits(18, 16),
+                    frac: None,
+                    enable: 1 << 28,
+                },
+            ),
+            cpu_b: (soc == Soc::G12b).then_some((
+                Pll {
+                    name: "sys1_pll",
+                    cntl: 0x380,
+                    m: bits(7, 0),
+                    n: bits(14, 10),
+                    od: bits(18, 16),
+                    frac: None,
+                    enable: 1 << 28,
+                },
+                HHI_SYS_CPUB_CLK_CNTL,
+            )),
+        },
+    }
+}
+
+const HHI_MPEG_CLK_CNTL: u32 = 0x174;
+const HHI_SYS_CPU_CLK_CNTL0: u32 = 0x19c;
+const HHI_SYS_CPU_CLK_CNTL1: u32 = 0x15c;
+const HHI_SYS_CPUB_CLK_CNTL: u32 = 0x208;
+
+fn mhz(hz: f64) -> String {
+    format!("{:.3} MHz", hz / 1_000_000.0)
+}
+
+struct Clocks<'a> {
+    h: &'a Handle,
+    t: Duration,
+    l: Layout,
+}
+
+// Each decoder returns the rate and how it comes about.
+type Rate = (f64, String);
+
+fn show(indent: &str, name: &str, (rate, detail): &Rate) {
+    println!("{indent}{name:<10} {:>14}  {detail}", mhz(*rate));
+}
+
+impl Clocks<'_> {
+    fn new(h: &Handle, t: Duration, soc: Soc) -> Clocks<'_> {
+        Clocks {
+            h,
+            t,
+            l: layout(soc),
+        }
+    }
+
+    fn read(&self, offs: u32) -> Result<u32, String> {
+        protocol::read_reg(self.h, self.t, self.l.hhi + offs)
+    }
+
+    fn pll(&self, base: u32, p: &Pll) -> Result<Rate, String> {
+        let read = |offs| protocol::read_reg(self.h, self.t, base + offs);
+        let v = read(p.cntl)?;
+        let (m, n, od) = (p.m.get(v), p.n.get(v), p.od.get(v));
+        let frac = match &p.frac {
+            Some((reg, b)) => b.get(read(*reg)?) as f64 / (1u64 << b.width()) as f64,
+            None => 0.0,
+        };
+        let rate = match n {
+            0 => 0.0,
+            n => XTAL * (m as f64 + frac) / n as f64 / (1 << od) as f64,
+        };
+        let state = match (v & p.enable != 0, v & PLL_LOCK != 0) {
+            (false, _) => "disabled",
+            (true, false) => "not locked",
+            (true, true) => "locked",
+        };
+        Ok((rate, format!("M {m} N {n} OD {od}, {state}")))
+    }
+
+    /// GX CPU clock: xtal or the SYS PLL, then undivided, halved, a third or
+    /// the scaler, then a final mux against xtal.
+    fn cpu_gx(&self, sys: f64) -> Result<Rate, String> {
+        let v = self.read(HHI_SYS_CPU_CLK_CNTL0)?;
+        if v & (1 << 7) == 0 {
+            return Ok((XTAL, "from xtal".to_string()));
+        }
+        let (src, parent) = match v & 1 != 0 {
+            true => ("sys_pll", sys),
+            false => ("xtal", XTAL),
+        };
+        let div = match (v >> 2) & 0x3 {
+            0 => 1,
+            1 => 2,
+            2 => 3,
+            // scale_div, an even divider
+            _ => 2 * ((self.read(HHI_SYS_CPU_CLK_CNTL1)? >> 20) & 0x3ff).max(1),
+        };
+        Ok((parent / div as f64, format!("from {src} / {div}")))
+    }
+
+    /// G12 CPU clock: either a SYS PLL or a dynamic mux of xtal and fixed
+    /// dividers, with a divider.
+    fn cpu_g12(&self, cntl: u32, sys: &Pll, rate: f64, fixed: f64) -> Result<Rate, String> {
+        let v = self.read(cntl)?;
+        if v & (1 << 11) != 0 {
+            return Ok((rate, format!("from {}", sys.name)));
+        }
+        // two channels, bit 10 selects
+        let shift = if v & (1 << 10) != 0 { 16 } else { 0 };
+        let ch = v >> shift;
+        let (src, parent) = match ch & 0x3 {
+            0 => ("xtal", XTAL),
+            1 => ("fclk_div2", fixed / 2.0),
+            2 => ("fclk_div3", fixed / 3.0),
+            _ => ("?", 0.0),
+        };
+        let div = match ch & (1 << 2) != 0 {
+            true => ((ch >> 4) & 0x3f) + 1,
+            false => 1,
+        };
+        Ok((parent / div as f64, format!("from {src} / {div}")))
+    }
+
+    fn mpll(&self, m: &Mpll, parent: f64) -> Result<Rate, String> {
+        let v = self.read(m.cntl)?;
+        let (sdm, n2) = (m.sdm.get(v) as f64, m.n2.get(v) as f64);
+        let den = n2 * SDM_DEN + sdm;
+        let rate = if den == 0.0 {
+            0.0
+        } else {
+            parent * SDM_DEN / den
+        };
+        Ok((rate, format!("N2 {n2} SDM {sdm}")))
+    }
+
+    fn mplls(&self, fixed: f64) -> Result<Vec<Rate>, String> {
+        self.l
+            .mplls
+            .iter()
+            .map(|m| self.mpll(m, fixed / 2.0))
+            .collect()
+    }
+
+    fn clk81(&self, fixed: f64, mpll: &[Rate]) -> Result<Rate, String> {
+        let v = self.read(HHI_MPEG_CLK_CNTL)?;
+        let (src, parent) = match (v >> 12) & 0x7 {
+            0 => ("xtal", XTAL),
+            2 => ("fclk_div7", fixed / 7.0),
+            3 => ("mpll1", mpll[1].0),
+            4 => ("mpll2", mpll[2].0),
+            5 => ("fclk_div4", fixed / 4.0),
+            6 => ("fclk_div3", fixed / 3.0),
+            7 => ("fclk_div5", fixed / 5.0),
+            _ => ("?", 0.0),
+        };
+        let div = (v & 0x7f) + 1;
+        let gate = if v & (1 << 7) != 0 { "" } else { ", gated" };
+        Ok((parent / div as f64, format!("from {src} / {div}{gate}")))
+    }
+}
+
+/// The rate of clk81, which clocks the peripherals, as currently set up.
+pub fn clk81(h: &Handle, t: Duration, soc: Soc) -> Result<f64, String> {
+    let c = Clocks::new(h, t, soc);
+    let (fixed, _) = c.pll(c.l.hhi, &c.l.fixed)?;
+    let mpll = c.mplls(fixed)?;
+    let (rate, _) = c.clk81(fixed, &mpll)?;
+    Ok(rate)
+}
+
+/// Read the PLLs and muxes and print the clock tree.
+pub fn print(h: &Handle, t: Duration, soc: Soc) -> Result<(), String> {
+    let c = Clocks::new(h, t, soc);
+    println!("{:<10} {:>14}", "xtal", mhz(XTAL));
+    let sys = c.pll(c.l.hhi, &c.l.sys)?;
+    show("  ", c.l.sys.name, &sys);
+    let fixed = c.pll(c.l.hhi, &c.l.fixed)?;
+    show("  ", c.l.fixed.name, &fixed);
+    for d in [2.0, 3.0, 4.0, 5.0, 7.0] {
+        println!(
+            "    {:<10} {:>14}",
+            format!("fclk_div{d}"),
+            mhz(fixed.0 / d)
+        );
+    }
+    let mpll = c.mplls(fixed.0)?;
+    for (m, r) in c.l.mplls.iter().zip(&mpll) {
+        show("    ", m.name, r);
+    }
+    show("    ", "clk81", &c.clk81(fixed.0, &mpll)?);
+    match c.l.g12 {
+        true => show(
+            "  ",
+            "cpu_clk",
+            &c.cpu_g12(HHI_SYS_CPU_CLK_CNTL0, &c.l.sys, sys.0, fixed.0)?,
+        ),
+        false => show("  ", "cpu_clk", &c.cpu_gx(sys.0)?),
+    }
+    if let Some((pll, cntl)) = &c.l.cpu_b {
+        let sys1 = c.pll(c.l.hhi, pll)?;
+        show("  ", pll.name, &sys1);
+        show("  ", "cpub_clk", &c.cpu_g12(*cntl, pll, sys1.0, fixed.0)?);
+    }
+    let (base, ddr) = &c.l.ddr;
+    show("  ", ddr.name, &c.pll(*base, ddr)?);
+    Ok(())
+}
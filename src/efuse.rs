@@ -0,0 +1,121 @@
+use crate::protocol::{self, Handle, Stage};
+use crate::regs::RegDb;
+use std::time::Duration;
+
+// eFuses
+//
+// The ROM copies the license fuses into the AO secure registers, so we read
+// that block and decode what the register database knows about it. U-Boot
+// runs non-secure and cannot read the block, so there we send its `efuse
+// read` command and keep the bytes of the reply. The vendor gadget may only
+// answer "success" and print the values on the serial console; then we say
+// so instead of saving nothing.
+
+const GX_SHADOW: u32 = 0xc810_0200;
+const G12_SHADOW: u32 = 0xff80_0200;
+const SHADOW_SIZE: u32 = 64;
+
+// offset and size for U-Boot's `efuse read`, the license area
+const UBOOT_OFFSET: u32 = 0;
+const UBOOT_SIZE: u32 = 0x100;
+
+// Asked for, but without a known shadow bit
+const UNKNOWN: [&str; 2] = ["USB boot disable", "JTAG disable"];
+
+/// Bytes of a hex dump as printed by U-Boot, e.g. `0x00: 12 34 ..`; the
+/// part up to a colon is an offset.
+fn parse_hex_dump(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for line in s.lines() {
+        let data = line.split_once(':').map_or(line, |(_, d)| d);
+        for tok in data.split_whitespace() {
+            match u8::from_str_radix(tok, 16) {
+                Ok(b) if tok.len() == 2 => bytes.push(b),
+                _ => break,
+            }
+        }
+    }
+    bytes
+}
+
+fn print_unknown() {
+    for u in UNKNOWN {
+        println!("  {u:<16} unknown, no fuse bit documented");
+    }
+}
+
+fn uboot_read(
+    h: &Handle,
+    t: Duration,
+    stage: Option<Stage>,
+    file_name: &str,
+) -> Result<(), String> {
+    let cmd = format!("efuse read {UBOOT_OFFSET:#x} {UBOOT_SIZE:#x}");
+    let reply = protocol::bulk_cmd(h, t, stage, &cmd)?;
+    let raw = parse_hex_dump(&reply);
+    if raw.is_empty() {
+        return Err(format!(
+            "U-Boot replied \"{reply}\" without fuse data, see its serial console"
+        ));
+    }
+    std::fs::write(file_name, &raw).map_err(|e| format!("{file_name}: {e}"))?;
+    println!("Saved {} bytes of fuses to {file_name}", raw.len());
+    for (i, c) in raw.chunks(16).enumerate() {
+        println!("  {:04x}: {c:02x?}", UBOOT_OFFSET as usize + 16 * i);
+    }
+    // The raw fuse layout differs from the shadow registers.
+    println!("  (raw fuses, not decoded)");
+    print_unknown();
+    Ok(())
+}
+
+/// Read the shadow registers, or the fuses in U-Boot, save them raw and
+/// print the decoded fields.
+pub fn report(
+    h: &Handle,
+    t: Duration,
+    db: &RegDb,
+    stage: Option<Stage>,
+    file_name: &str,
+) -> Result<(), String> {
+    if stage == Some(Stage::Tpl) {
+        return uboot_read(h, t, stage, file_name);
+    }
+    let base = match db.soc.is_g12() {
+        true => G12_SHADOW,
+        false => GX_SHADOW,
+    };
+    let words = protocol::read_block(h, t, base)?;
+    let raw: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    std::fs::write(file_name, &raw).map_err(|e| format!("{file_name}: {e}"))?;
+    println!("Saved {} bytes from {base:08x} to {file_name}", raw.len());
+
+    for (i, w) in words.chunks(4).enumerate() {
+        println!("  {:08x}: {:08x?}", base + 16 * i as u32, w);
+    }
+    println!();
+    let known = db
+        .regs
+        .iter()
+        .filter(|r| (base..base + SHADOW_SIZE).contains(&r.addr) && !r.fields.is_empty());
+    for r in known {
+        r.print(words[((r.addr - base) / 4) as usize]);
+    }
+    print_unknown();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump() {
+        let s = "efuse read 0x0 0x8\n0x00: 01 02 a0 ff\n0x04: 00 10 20 30 success\n";
+        assert_eq!(
+            parse_hex_dump(s),
+            [0x01, 0x02, 0xa0, 0xff, 0x00, 0x10, 0x20, 0x30]
+        );
+        assert!(parse_hex_dump("success").is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::protocol::{self, Handle};
+use clap::ValueEnum;
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+// Minimal GDB remote serial protocol server on top of the loader commands.
+// See https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+//
+// Memory reads and writes work; registers are reported as unavailable.
+// Setting the PC (e.g. via `jump *0x...`) and continuing executes code there.
+// The architecture is sent to GDB as target description, so that both agree
+// on which register is the PC.
+//
+//   gdb-multiarch -ex 'target remote :3333'
+
+// The loader reads 64 bytes at a time.
+const BLOCK_SIZE: u32 = 64;
+// hex encoded, so this allows for 1k of memory per packet
+const PACKET_SIZE: usize = 0x800;
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Arch {
+    Aarch64,
+    Arm,
+}
+
+impl Arch {
+    /// PC register number in GDB's default description of the architecture
+    fn pc(&self) -> usize {
+        match self {
+            Self::Aarch64 => 32,
+            Self::Arm => 15,
+        }
+    }
+
+    fn target_xml(&self) -> String {
+        let a = match self {
+            Self::Aarch64 => "aarch64",
+            Self::Arm => "arm",
+        };
+        format!("<?xml version=\"1.0\"?><target><architecture>{a}</architecture></target>")
+    }
+}
+
+// SIGTRAP
+const STOP_REPLY: &str = "S05";
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |a, &b| a.wrapping_add(b))
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd hex length: {s}"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// Parse `addr,len` as in `m` and `M` packets.
+fn addr_len(s: &str) -> Result<(u32, u32), String> {
+    let (a, l) = s.split_once(',').ok_or("missing ','")?;
+    let a = u64::from_str_radix(a, 16).map_err(|e| e.to_string())?;
+    let l = u32::from_str_radix(l, 16).map_err(|e| e.to_string())?;
+    let a = u32::try_from(a).map_err(|_| format!("address out of range: {a:x}"))?;
+    Ok((a, l))
+}
+
+/// Read arbitrary memory through aligned 64 byte block reads.
+fn read_memory(h: &Handle, t: Duration, addr: u32, len: u32) -> Result<Vec<u8>, String> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let start = addr & !(BLOCK_SIZE - 1);
+    let end = addr.checked_add(len).ok_or("address overflow")?;
+    let mut buf = Vec::new();
+    for a in (start..end).step_by(BLOCK_SIZE as usize) {
+        for w in protocol::read_block(h, t, a)? {
+            buf.extend(w.to_le_bytes());
+        }
+    }
+    let offs = (addr - start) as usize;
+    Ok(buf[offs..offs + len as usize].to_vec())
+}
+
+/// Write arbitrary memory. Aligned 64 byte blocks go out as they are, the
+/// rest is done as read-modify-write of 32-bit words.
+fn write_memory(h: &Handle, t: Duration, addr: u32, data: &[u8]) -> Result<(), String> {
+    let mut a = addr;
+    let mut rest = data;
+    while !rest.is_empty() {
+        if a.is_multiple_of(BLOCK_SIZE) && rest.len() >= BLOCK_SIZE as usize {
+            let (block, r) = rest.split_at(BLOCK_SIZE as usize);
+            protocol::write(h, t, block, a)?;
+            a += BLOCK_SIZE;
+            rest = r;
+            continue;
+        }
+        let word = a & !3;
+        let offs = (a - word) as usize;
+        let n = (4 - offs).min(rest.len());
+        let mut v = if offs == 0 && n == 4 {
+            [0u8; 4]
+        } else {
+            protocol::read_reg(h, t, word)?.to_le_bytes()
+        };
+        v[offs..offs + n].copy_from_slice(&rest[..n]);
+        protocol::write_reg(h, t, word, u32::from_le_bytes(v))?;
+        a += n as u32;
+        rest = &rest[n..];
+    }
+    Ok(())
+}
+
+struct Server<'a> {
+    h: &'a Handle,
+    t: Duration,
+    arch: Arch,
+    // set by GDB writing the PC register, used on continue
+    pc: Option<u32>,
+    detached: bool,
+}
+
+impl Server<'_> {
+    fn continue_at(&mut self, addr: Option<u32>) -> String {
+        match addr.or(self.pc.take()) {
+            Some(a) => match protocol::exec(self.h, self.t, a) {
+                Ok(_) => STOP_REPLY.to_string(),
+                Err(_) => "E01".to_string(),
+            },
+            // We cannot read the PC, so there is nowhere to continue.
+            None => "E01".to_string(),
+        }
+    }
+
+    /// Handle a packet, returning the reply, or None to close the connection.
+    fn handle(&mut self, p: &str) -> Option<String> {
+        let r = match p.split_at(p.len().min(1)) {
+            ("?", _) => STOP_REPLY.to_string(),
+            ("q", q) if q.starts_with("Supported") => {
+                format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+")
+            }
+            ("q", q) if q.starts_with("Xfer:features:read:target.xml:") => {
+                let xml = self.arch.target_xml();
+                match q.rsplit(':').next().and_then(|ol| addr_len(ol).ok()) {
+                    Some((o, l)) => {
+                        let rest = xml.get(o as usize..).unwrap_or("");
+                        match rest.len() > l as usize {
+                            true => format!("m{}", &rest[..l as usize]),
+                            false => format!("l{rest}"),
+                        }
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            ("q", "Attached") => "1".to_string(),
+            ("q", "C") => "QC1".to_string(),
+            ("q", "fThreadInfo") => "m1".to_string(),
+            ("q", "sThreadInfo") => "l".to_string(),
+            ("H", _) => "OK".to_string(),
+            // No registers available; GDB marks the missing ones as such.
+            ("g", _) => "xxxxxxxx".to_string(),
+            ("p", _) => "xxxxxxxxxxxxxxxx".to_string(),
+            ("P", a) => {
+                let Some((n, v)) = a.split_once('=') else {
+                    return Some("E01".to_string());
+                };
+                match (usize::from_str_radix(n, 16), unhex(v)) {
+                    (Ok(n), Ok(v)) if n == self.arch.pc() && v.len() >= 4 => {
+                        self.pc = Some(u32::from_le_bytes(v[..4].try_into().unwrap()));
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            ("m", a) => match addr_len(a).and_then(|(a, l)| read_memory(self.h, self.t, a, l)) {
+                Ok(buf) => hex(&buf),
+                Err(e) => {
+                    println!("gdb: m{a}: {e}");
+                    "E01".to_string()
+                }
+            },
+            ("M", a) => {
+                let res = a
+                    .split_once(':')
+                    .ok_or("missing ':'".to_string())
+                    .and_then(|(al, d)| Ok((addr_len(al)?, unhex(d)?)))
+                    .and_then(|((a, _), d)| write_memory(self.h, self.t, a, &d));
+                match res {
+                    Ok(_) => "OK".to_string(),
+                    Err(e) => {
+                        println!("gdb: M: {e}");
+                        "E01".to_string()
+                    }
+                }
+            }
+            ("c", a) => {
+                let a = u32::from_str_radix(a, 16).ok();
+                self.continue_at(a)
+            }
+            ("v", "Cont?") => "vCont;c".to_string(),
+            ("v", c) if c.starts_with("Cont;c") => self.continue_at(None),
+            ("k", _) => return None,
+            ("D", _) => {
+                self.detached = true;
+                "OK".to_string()
+            }
+            // Anything else, e.g. breakpoints or single-step, is unsupported.
+            _ => String::new(),
+        };
+        Some(r)
+    }
+}
+
+fn send(s: &mut TcpStream, data: &str) -> std::io::Result<()> {
+    let p = format!("${data}#{:02x}", checksum(data.as_bytes()));
+    s.write_all(p.as_bytes())
+}
+
+fn serve(h: &Handle, t: Duration, arch: Arch, stream: TcpStream) -> std::io::Result<()> {
+    let mut out = stream.try_clone()?;
+    let mut bytes = BufReader::new(stream).bytes();
+    let mut server = Server {
+        h,
+        t,
+        arch,
+        pc: None,
+        detached: false,
+    };
+    while let Some(b) = bytes.next() {
+        match b? {
+            b'$' => {
+                let mut data = Vec::new();
+                for b in bytes.by_ref() {
+                    match b? {
+                        b'#' => break,
+                        b => data.push(b),
+                    }
+                }
+                let mut cs = [0u8; 2];
+                for c in cs.iter_mut() {
+                    *c = bytes.next().transpose()?.unwrap_or(0);
+                }
+                let cs = u8::from_str_radix(&String::from_utf8_lossy(&cs), 16).ok();
+                if cs != Some(checksum(&data)) {
+                    out.write_all(b"-")?;
+                    continue;
+                }
+                out.write_all(b"+")?;
+                let p = String::from_utf8_lossy(&data);
+                match server.handle(&p) {
+                    Some(r) => send(&mut out, &r)?,
+                    None => return Ok(()),
+                }
+                if server.detached {
+                    return Ok(());
+                }
+            }
+            // Ctrl-C from GDB; nothing is running, so just report a stop.
+            0x03 => send(&mut out, STOP_REPLY)?,
+            // acks
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Listen on the given address, `:port` meaning localhost, and serve GDB
+/// clients one after another.
+pub fn run(h: &Handle, t: Duration, addr: &str, arch: Arch) -> Result<(), String> {
+    let addr = match addr.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{port}"),
+        None => addr.to_string(),
+    };
+    let listener = TcpListener::bind(&addr).map_err(|e| format!("{addr}: {e}"))?;
+    println!("Waiting for GDB on {addr}");
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| e.to_string())?;
+        println!("gdb: connection from {:?}", stream.peer_addr());
+        if let Err(e) = serve(h, t, arch, stream) {
+            println!("gdb: {e}");
+        }
+        println!("gdb: connection closed");
+    }
+    Ok(())
+}
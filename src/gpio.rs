@@ -0,0 +1,295 @@
+use crate::protocol::{self, Handle};
+use crate::soc::Soc;
+use clap::ValueEnum;
+use std::time::Duration;
+
+// GPIO banks of the Meson SoCs, after Linux drivers/pinctrl/meson/
+//
+// Each bank has its bits in five registers: pull enable, pull direction,
+// output enable (active low), output level and input level. Registers are
+// given as word offset from the domain's base address, plus the bit of the
+// first pin in the bank.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Domain {
+    /// always-on domain
+    Ao,
+    /// everything else
+    Ee,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RegBit {
+    pub reg: u8,
+    pub bit: u8,
+}
+
+const fn rb(reg: u8, bit: u8) -> RegBit {
+    RegBit { reg, bit }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Bank {
+    pub name: &'static str,
+    pub pins: u8,
+    pub domain: Domain,
+    pub pull_en: RegBit,
+    pub pull: RegBit,
+    pub dir: RegBit,
+    pub out: RegBit,
+    pub input: RegBit,
+}
+
+const fn bank(name: &'static str, pins: u8, domain: Domain, regs: [RegBit; 5]) -> Bank {
+    let [pull_en, pull, dir, out, input] = regs;
+    Bank {
+        name,
+        pins,
+        domain,
+        pull_en,
+        pull,
+        dir,
+        out,
+        input,
+    }
+}
+
+/// Base addresses of the register ranges per domain
+#[derive(Copy, Clone, Debug)]
+pub struct Bases {
+    pub gpio: u32,
+    pub pull: u32,
+    pub pull_en: u32,
+}
+
+pub struct Layout {
+    pub ao: Bases,
+    pub ee: Bases,
+    pub banks: &'static [Bank],
+}
+
+use Domain::{Ao, Ee};
+
+// name, pins, domain, [pull enable, pull, dir, out, in]
+#[rustfmt::skip]
+const GXBB_BANKS: [Bank; 9] = [
+    bank("GPIOX",   23, Ee, [rb(4, 0), rb(4, 0), rb(12, 0), rb(13, 0), rb(14, 0)]),
+    bank("GPIOY",   17, Ee, [rb(1, 0), rb(1, 0), rb(3, 0), rb(4, 0), rb(5, 0)]),
+    bank("GPIODV",  30, Ee, [rb(0, 0), rb(0, 0), rb(0, 0), rb(1, 0), rb(2, 0)]),
+    bank("GPIOH",   4,  Ee, [rb(1, 20), rb(1, 20), rb(3, 20), rb(4, 20), rb(5, 20)]),
+    bank("GPIOZ",   16, Ee, [rb(3, 0), rb(3, 0), rb(9, 0), rb(10, 0), rb(11, 0)]),
+    bank("CARD",    7,  Ee, [rb(2, 20), rb(2, 20), rb(6, 20), rb(7, 20), rb(8, 20)]),
+    bank("BOOT",    18, Ee, [rb(2, 0), rb(2, 0), rb(6, 0), rb(7, 0), rb(8, 0)]),
+    bank("GPIOCLK", 4,  Ee, [rb(3, 28), rb(3, 28), rb(9, 28), rb(10, 28), rb(11, 28)]),
+    bank("GPIOAO",  14, Ao, [rb(0, 16), rb(0, 0), rb(0, 0), rb(0, 16), rb(1, 0)]),
+];
+
+#[rustfmt::skip]
+const GXL_BANKS: [Bank; 8] = [
+    bank("GPIOX",   19, Ee, [rb(4, 0), rb(4, 0), rb(12, 0), rb(13, 0), rb(14, 0)]),
+    bank("GPIODV",  30, Ee, [rb(0, 0), rb(0, 0), rb(0, 0), rb(1, 0), rb(2, 0)]),
+    bank("GPIOH",   10, Ee, [rb(1, 20), rb(1, 20), rb(3, 20), rb(4, 20), rb(5, 20)]),
+    bank("GPIOZ",   16, Ee, [rb(3, 0), rb(3, 0), rb(9, 0), rb(10, 0), rb(11, 0)]),
+    bank("CARD",    7,  Ee, [rb(2, 20), rb(2, 20), rb(6, 20), rb(7, 20), rb(8, 20)]),
+    bank("BOOT",    16, Ee, [rb(2, 0), rb(2, 0), rb(6, 0), rb(7, 0), rb(8, 0)]),
+    bank("GPIOCLK", 2,  Ee, [rb(3, 28), rb(3, 28), rb(9, 28), rb(10, 28), rb(11, 28)]),
+    bank("GPIOAO",  10, Ao, [rb(0, 16), rb(0, 0), rb(0, 0), rb(0, 16), rb(1, 0)]),
+];
+
+// G12B and SM1 share this with G12A.
+#[rustfmt::skip]
+const G12A_BANKS: [Bank; 8] = [
+    bank("GPIOZ",   16, Ee, [rb(4, 0), rb(4, 0), rb(12, 0), rb(13, 0), rb(14, 0)]),
+    bank("GPIOH",   9,  Ee, [rb(3, 0), rb(3, 0), rb(9, 0), rb(10, 0), rb(11, 0)]),
+    bank("BOOT",    16, Ee, [rb(0, 0), rb(0, 0), rb(0, 0), rb(1, 0), rb(2, 0)]),
+    bank("GPIOC",   8,  Ee, [rb(1, 0), rb(1, 0), rb(3, 0), rb(4, 0), rb(5, 0)]),
+    bank("GPIOA",   16, Ee, [rb(5, 0), rb(5, 0), rb(16, 0), rb(17, 0), rb(18, 0)]),
+    bank("GPIOX",   20, Ee, [rb(2, 0), rb(2, 0), rb(6, 0), rb(7, 0), rb(8, 0)]),
+    bank("GPIOAO",  12, Ao, [rb(3, 0), rb(2, 0), rb(0, 0), rb(4, 0), rb(1, 0)]),
+    bank("GPIOE",   3,  Ao, [rb(3, 16), rb(2, 16), rb(0, 16), rb(4, 16), rb(1, 16)]),
+];
+
+// On GX, the AO pull registers are separate from the GPIO registers, and
+// pull enable (upper half) and direction (lower half) share one register.
+const GX_AO: Bases = Bases {
+    gpio: 0xc810_0024,
+    pull: 0xc810_002c,
+    pull_en: 0xc810_002c,
+};
+
+const GX_EE: Bases = Bases {
+    gpio: 0xc883_4430,
+    pull: 0xc883_44e8,
+    pull_en: 0xc883_4520,
+};
+
+const G12_AO: Bases = Bases {
+    gpio: 0xff80_0024,
+    pull: 0xff80_0024,
+    pull_en: 0xff80_0024,
+};
+
+const G12_EE: Bases = Bases {
+    gpio: 0xff63_4440,
+    pull: 0xff63_44e8,
+    pull_en: 0xff63_4520,
+};
+
+pub fn layout(soc: Soc) -> Layout {
+    match soc {
+        Soc::Gxbb => Layout {
+            ao: GX_AO,
+            ee: GX_EE,
+            banks: &GXBB_BANKS,
+        },
+        Soc::Gxl | Soc::Gxm => Layout {
+            ao: GX_AO,
+            ee: GX_EE,
+            banks: &GXL_BANKS,
+        },
+        Soc::G12a | Soc::G12b | Soc::Sm1 => Layout {
+            ao: G12_AO,
+            ee: G12_EE,
+            banks: &G12A_BANKS,
+        },
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    In,
+    Out,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// A pin, resolved to its bank
+#[derive(Copy, Clone, Debug)]
+pub struct Pin {
+    pub bank: Bank,
+    pub n: u8,
+    bases: Bases,
+}
+
+impl std::fmt::Display for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.bank.name, self.n)
+    }
+}
+
+impl Pin {
+    fn addr(&self, base: u32, r: RegBit) -> (u32, u32) {
+        (base + 4 * r.reg as u32, 1 << (r.bit + self.n))
+    }
+
+    pub fn pull_en(&self) -> (u32, u32) {
+        self.addr(self.bases.pull_en, self.bank.pull_en)
+    }
+
+    pub fn pull(&self) -> (u32, u32) {
+        self.addr(self.bases.pull, self.bank.pull)
+    }
+
+    pub fn dir(&self) -> (u32, u32) {
+        self.addr(self.bases.gpio, self.bank.dir)
+    }
+
+    pub fn out(&self) -> (u32, u32) {
+        self.addr(self.bases.gpio, self.bank.out)
+    }
+
+    pub fn input(&self) -> (u32, u32) {
+        self.addr(self.bases.gpio, self.bank.input)
+    }
+}
+
+/// Look up a pin by name, e.g. GPIOAO_9 or BOOT_3.
+pub fn pin(soc: Soc, name: &str) -> Result<Pin, String> {
+    let l = layout(soc);
+    let name = name.to_ascii_uppercase();
+    let (b, n) = name
+        .rsplit_once('_')
+        .ok_or(format!("Expected BANK_N, got {name}"))?;
+    let bank = *l
+        .banks
+        .iter()
+        .find(|k| k.name == b)
+        .ok_or(format!("No bank {b} on {soc}"))?;
+    let n: u8 = n.parse().map_err(|_| format!("Bad pin number in {name}"))?;
+    if n >= bank.pins {
+        return Err(format!("{b} has {} pins only on {soc}", bank.pins));
+    }
+    let bases = match bank.domain {
+        Domain::Ao => l.ao,
+        Domain::Ee => l.ee,
+    };
+    Ok(Pin { bank, n, bases })
+}
+
+fn read_bit(h: &Handle, t: Duration, (addr, mask): (u32, u32)) -> Result<bool, String> {
+    Ok(protocol::read_reg(h, t, addr)? & mask != 0)
+}
+
+fn write_bit(h: &Handle, t: Duration, (addr, mask): (u32, u32), v: bool) -> Result<(), String> {
+    match v {
+        true => protocol::modify_reg(h, t, addr, 0, mask, 0)?,
+        false => protocol::modify_reg(h, t, addr, mask, 0, 0)?,
+    };
+    Ok(())
+}
+
+pub fn get_level(h: &Handle, t: Duration, p: &Pin) -> Result<Level, String> {
+    match read_bit(h, t, p.input())? {
+        true => Ok(Level::High),
+        false => Ok(Level::Low),
+    }
+}
+
+/// Drive a pin: set the output level first, then enable the output.
+pub fn set(h: &Handle, t: Duration, p: &Pin, l: Level) -> Result<(), String> {
+    write_bit(h, t, p.out(), l == Level::High)?;
+    set_mode(h, t, p, Mode::Out)
+}
+
+pub fn set_mode(h: &Handle, t: Duration, p: &Pin, m: Mode) -> Result<(), String> {
+    // output enable is active low
+    write_bit(h, t, p.dir(), m == Mode::In)
+}
+
+pub fn set_pull(h: &Handle, t: Duration, p: &Pin, pull: Pull) -> Result<(), String> {
+    match pull {
+        Pull::None => write_bit(h, t, p.pull_en(), false),
+        Pull::Up | Pull::Down => {
+            write_bit(h, t, p.pull(), pull == Pull::Up)?;
+            write_bit(h, t, p.pull_en(), true)
+        }
+    }
+}
+
+/// Print the state of a pin.
+pub fn get(h: &Handle, t: Duration, p: &Pin) -> Result<(), String> {
+    let level = get_level(h, t, p)?;
+    let mode = match read_bit(h, t, p.dir())? {
+        true => Mode::In,
+        false => Mode::Out,
+    };
+    let out = read_bit(h, t, p.out())?;
+    let pull = match (read_bit(h, t, p.pull_en())?, read_bit(h, t, p.pull())?) {
+        (false, _) => Pull::None,
+        (true, true) => Pull::Up,
+        (true, false) => Pull::Down,
+    };
+    println!("{p}: {level:?}, mode {mode:?}, output {out}, pull {pull:?}");
+    Ok(())
+}
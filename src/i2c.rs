@@ -0,0 +1,272 @@
+use crate::clocks;
+use crate::pinmux;
+use crate::protocol::{self, Handle};
+use crate::soc::Soc;
+use std::time::{Duration, Instant};
+
+// Meson I2C master, after Linux drivers/i2c/busses/i2c-meson.c
+//
+// The controller runs a list of up to 16 tokens per transfer, with up to 8
+// bytes of write data and 8 bytes of read data in registers. We only use
+// register accesses from the host, so the loader can talk to PMICs and
+// EEPROMs before any firmware runs.
+
+const REG_CTRL: u32 = 0x00;
+const REG_SLAVE_ADDR: u32 = 0x04;
+const REG_TOK_LIST0: u32 = 0x08;
+const REG_TOK_LIST1: u32 = 0x0c;
+const REG_TOK_WDATA0: u32 = 0x10;
+const REG_TOK_WDATA1: u32 = 0x14;
+const REG_TOK_RDATA0: u32 = 0x18;
+const REG_TOK_RDATA1: u32 = 0x1c;
+
+const CTRL_START: u32 = 1 << 0;
+const CTRL_STATUS: u32 = 1 << 2;
+const CTRL_ERROR: u32 = 1 << 3;
+const CTRL_CLKDIV_SHIFT: u32 = 12;
+const CTRL_CLKDIV_MASK: u32 = 0x3ff << CTRL_CLKDIV_SHIFT;
+const CTRL_CLKDIVEXT_SHIFT: u32 = 28;
+const CTRL_CLKDIVEXT_MASK: u32 = 0x3 << CTRL_CLKDIVEXT_SHIFT;
+
+const SLAVE_ADDR_MASK: u32 = 0xff;
+const SLAVE_SCL_LOW_EN: u32 = 1 << 28;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(u32)]
+enum Token {
+    End = 0,
+    Start = 1,
+    AddrWrite = 2,
+    AddrRead = 3,
+    Data = 4,
+    DataLast = 5,
+    Stop = 6,
+}
+
+const MAX_TOKENS: usize = 16;
+const MAX_DATA: usize = 8;
+
+// SCL, divided from clk81 as the ROM or U-Boot left it
+const FREQ: u32 = 100_000;
+
+const TRANSFER_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A bus: controller base, clock gate and the pins with their functions
+pub struct Bus {
+    pub name: &'static str,
+    base: u32,
+    gate: (u32, u32),
+    pins: [(&'static str, &'static str); 2],
+}
+
+const fn bus(
+    name: &'static str,
+    base: u32,
+    gate: (u32, u32),
+    pins: [(&'static str, &'static str); 2],
+) -> Bus {
+    Bus {
+        name,
+        base,
+        gate,
+        pins,
+    }
+}
+
+// HHI_GCLK_MPEG0 bit 9 gates the EE controllers; the AO controller has its
+// own gate in the AO domain.
+const GX_EE_GATE: (u32, u32) = (0xc883_c140, 1 << 9);
+const GX_AO_GATE: (u32, u32) = (0xc810_0040, 1 << 1);
+const G12_EE_GATE: (u32, u32) = (0xff63_c140, 1 << 9);
+const G12_AO_GATE: (u32, u32) = (0xff80_004c, 1 << 2);
+
+#[rustfmt::skip]
+const GXBB_BUSES: [Bus; 1] = [
+    bus("ao", 0xc810_0500, GX_AO_GATE, [("GPIOAO_4", "i2c_sck_ao"), ("GPIOAO_5", "i2c_sda_ao")]),
+];
+
+#[rustfmt::skip]
+const GXL_BUSES: [Bus; 4] = [
+    bus("ao", 0xc810_0500, GX_AO_GATE, [("GPIOAO_4", "i2c_sck_ao"), ("GPIOAO_5", "i2c_sda_ao")]),
+    bus("a",  0xc110_8500, GX_EE_GATE, [("GPIODV_25", "i2c_sck_a"), ("GPIODV_24", "i2c_sda_a")]),
+    bus("b",  0xc110_87c0, GX_EE_GATE, [("GPIODV_27", "i2c_sck_b"), ("GPIODV_26", "i2c_sda_b")]),
+    bus("c",  0xc110_87e0, GX_EE_GATE, [("GPIODV_19", "i2c_sck_c"), ("GPIODV_18", "i2c_sda_c")]),
+];
+
+#[rustfmt::skip]
+const G12_BUSES: [Bus; 3] = [
+    bus("ao", 0xff80_5000, G12_AO_GATE, [("GPIOAO_2", "i2c_ao_sck"), ("GPIOAO_3", "i2c_ao_sda")]),
+    bus("3a", 0xffd1_c000, G12_EE_GATE, [("GPIOA_15", "i2c3_sck_a"), ("GPIOA_14", "i2c3_sda_a")]),
+    bus("3h", 0xffd1_c000, G12_EE_GATE, [("GPIOH_7", "i2c3_sck_h"), ("GPIOH_6", "i2c3_sda_h")]),
+];
+
+fn buses(soc: Soc) -> &'static [Bus] {
+    match soc {
+        Soc::Gxbb => &GXBB_BUSES,
+        Soc::Gxl | Soc::Gxm => &GXL_BUSES,
+        Soc::G12a | Soc::G12b | Soc::Sm1 => &G12_BUSES,
+    }
+}
+
+pub fn bus_by_name(soc: Soc, name: &str) -> Result<&'static Bus, String> {
+    let all = buses(soc);
+    all.iter()
+        .find(|b| b.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = all.iter().map(|b| b.name).collect();
+            format!("No I2C bus {name} on {soc}, available: {}", names.join(" "))
+        })
+}
+
+pub struct I2c<'a> {
+    h: &'a Handle,
+    t: Duration,
+    bus: &'static Bus,
+}
+
+impl<'a> I2c<'a> {
+    /// Ungate the controller clock, mux the pins and set the bus clock.
+    pub fn init(h: &'a Handle, t: Duration, soc: Soc, bus: &'static Bus) -> Result<Self, String> {
+        let (addr, mask) = bus.gate;
+        protocol::modify_reg(h, t, addr, 0, mask, 0)?;
+        for (pin, function) in bus.pins {
+            pinmux::set(h, t, soc, pin, function)?;
+        }
+        // G12 has the AXG variant of the controller, dividing by 3.
+        let factor = if soc.is_g12() { 3 } else { 4 };
+        let clk81 = clocks::clk81(h, t, soc)? as u32;
+        if clk81 == 0 {
+            return Err("clk81 is not running".to_string());
+        }
+        let div = clk81.div_ceil(FREQ * factor);
+        let set = ((div & 0x3ff) << CTRL_CLKDIV_SHIFT) | ((div >> 10) << CTRL_CLKDIVEXT_SHIFT);
+        let clear = CTRL_CLKDIV_MASK | CTRL_CLKDIVEXT_MASK | CTRL_START;
+        protocol::modify_reg(h, t, bus.base + REG_CTRL, clear, set, 0)?;
+        // Use the divider from the control register for SCL low time.
+        protocol::modify_reg(h, t, bus.base + REG_SLAVE_ADDR, SLAVE_SCL_LOW_EN, 0, 0)?;
+        Ok(I2c { h, t, bus })
+    }
+
+    fn reg(&self, offs: u32) -> u32 {
+        self.bus.base + offs
+    }
+
+    /// Run a token list, return the bytes read, or an error on NACK.
+    fn transfer(&self, addr: u8, tokens: &[Token], wdata: &[u8]) -> Result<Vec<u8>, String> {
+        let (h, t) = (self.h, self.t);
+        if tokens.len() > MAX_TOKENS || wdata.len() > MAX_DATA {
+            return Err("I2C transfer too long".to_string());
+        }
+        let mut list = [0u32; 2];
+        for (i, tok) in tokens.iter().enumerate() {
+            list[i / 8] |= (*tok as u32) << (4 * (i % 8));
+        }
+        let mut data = [0u8; MAX_DATA];
+        data[..wdata.len()].copy_from_slice(wdata);
+        let (w0, w1) = data.split_at(4);
+        protocol::write_reg(h, t, self.reg(REG_TOK_LIST0), list[0])?;
+        protocol::write_reg(h, t, self.reg(REG_TOK_LIST1), list[1])?;
+        protocol::write_reg(
+            h,
+            t,
+            self.reg(REG_TOK_WDATA0),
+            u32::from_le_bytes(w0.try_into().unwrap()),
+        )?;
+        protocol::write_reg(
+            h,
+            t,
+            self.reg(REG_TOK_WDATA1),
+            u32::from_le_bytes(w1.try_into().unwrap()),
+        )?;
+        let slave = (addr as u32) << 1;
+        protocol::modify_reg(h, t, self.reg(REG_SLAVE_ADDR), SLAVE_ADDR_MASK, slave, 0)?;
+
+        // The controller starts on the rising edge of START.
+        protocol::modify_reg(h, t, self.reg(REG_CTRL), CTRL_START, 0, 0)?;
+        protocol::modify_reg(h, t, self.reg(REG_CTRL), 0, CTRL_START, 0)?;
+        let start = Instant::now();
+        let ctrl = loop {
+            let v = protocol::read_reg(h, t, self.reg(REG_CTRL))?;
+            if v & CTRL_STATUS == 0 {
+                break v;
+            }
+            if start.elapsed() > TRANSFER_TIMEOUT {
+                return Err(format!("I2C transfer to {addr:02x} timed out"));
+            }
+        };
+        if ctrl & CTRL_ERROR != 0 {
+            return Err(format!("No ACK from {addr:02x}"));
+        }
+
+        let count = tokens
+            .iter()
+            .filter(|t| matches!(t, Token::Data | Token::DataLast))
+            .count()
+            - wdata.len();
+        let r0 = protocol::read_reg(h, t, self.reg(REG_TOK_RDATA0))?;
+        let r1 = protocol::read_reg(h, t, self.reg(REG_TOK_RDATA1))?;
+        let mut rdata = [r0.to_le_bytes(), r1.to_le_bytes()].concat();
+        rdata.truncate(count);
+        Ok(rdata)
+    }
+
+    /// Check whether a device acknowledges its address.
+    pub fn probe(&self, addr: u8) -> Result<bool, String> {
+        use Token::*;
+        match self.transfer(addr, &[Start, AddrWrite, Stop, End], &[]) {
+            Ok(_) => Ok(true),
+            Err(e) if e.starts_with("No ACK") => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the register number, then read `count` bytes.
+    pub fn read(&self, addr: u8, reg: u8, count: usize) -> Result<Vec<u8>, String> {
+        use Token::*;
+        if count == 0 || count > MAX_DATA {
+            return Err(format!("Can read 1 to {MAX_DATA} bytes"));
+        }
+        let mut tokens = vec![Start, AddrWrite, Data, Start, AddrRead];
+        tokens.extend(std::iter::repeat_n(Data, count - 1));
+        tokens.extend([DataLast, Stop, End]);
+        // The register number is the one byte of write data.
+        let mut rdata = self.transfer(addr, &tokens, &[reg])?;
+        rdata.truncate(count);
+        Ok(rdata)
+    }
+
+    /// Write the register number, followed by data.
+    pub fn write(&self, addr: u8, reg: u8, data: &[u8]) -> Result<(), String> {
+        use Token::*;
+        if data.len() >= MAX_DATA {
+            return Err(format!("Can write {} bytes at most", MAX_DATA - 1));
+        }
+        let mut tokens = vec![Start, AddrWrite];
+        tokens.extend(std::iter::repeat_n(Data, data.len() + 1));
+        tokens.extend([Stop, End]);
+        let wdata = [&[reg], data].concat();
+        self.transfer(addr, &tokens, &wdata)?;
+        Ok(())
+    }
+}
+
+/// Probe all 7-bit addresses and print a table like i2cdetect.
+pub fn scan(i2c: &I2c) -> Result<(), String> {
+    println!("     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f");
+    for row in (0..0x80u8).step_by(16) {
+        print!("{row:02x}:");
+        for addr in row..row + 16 {
+            // reserved addresses
+            if !(0x08..0x78).contains(&addr) {
+                print!("   ");
+                continue;
+            }
+            match i2c.probe(addr)? {
+                true => print!(" {addr:02x}"),
+                false => print!(" --"),
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use crate::protocol::{self, Handle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+
+// Register change journal
+//
+// While a journal is active, the first write to each register records the
+// value it had before. The guard writes the original values back when it is
+// dropped: on normal exit, on error, and on Ctrl-C, which makes the next
+// register access fail so that the command returns; see `run`.
+
+static JOURNAL: Mutex<Option<Vec<(u32, u32)>>> = Mutex::new(None);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HANDLER: Once = Once::new();
+
+fn on_sigint() {
+    let pending = JOURNAL
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|j| !j.is_empty());
+    // Nothing to restore, or pressed twice: just quit.
+    if !pending || INTERRUPTED.swap(true, Ordering::SeqCst) {
+        std::process::exit(130);
+    }
+    println!("\nInterrupted, restoring registers...");
+}
+
+/// Fail register accesses after Ctrl-C.
+pub fn check() -> Result<(), String> {
+    match INTERRUPTED.load(Ordering::SeqCst) {
+        true => Err("Interrupted".to_string()),
+        false => Ok(()),
+    }
+}
+
+/// Record the original value of a register, if not known yet.
+pub fn note(addr: u32, val: u32) {
+    if let Some(j) = JOURNAL.lock().unwrap().as_mut() {
+        if !j.iter().any(|(a, _)| *a == addr) {
+            j.push((addr, val));
+        }
+    }
+}
+
+/// Read and record the original value of a register before it is written.
+pub fn record(h: &Handle, t: Duration, addr: u32) -> Result<(), String> {
+    let mut journal = JOURNAL.lock().unwrap();
+    if let Some(j) = journal.as_mut() {
+        if !j.iter().any(|(a, _)| *a == addr) {
+            j.push((addr, protocol::read_reg(h, t, addr)?));
+        }
+    }
+    Ok(())
+}
+
+/// Run `f` with a journal, restore the registers, and exit on Ctrl-C.
+pub fn run<F>(h: &Handle, t: Duration, keep: bool, f: F) -> Result<(), String>
+where
+    F: FnOnce() -> Result<(), String>,
+{
+    let guard = Guard::start(h, t, keep);
+    let r = f();
+    let interrupted = INTERRUPTED.load(Ordering::SeqCst);
+    drop(guard);
+    if interrupted {
+        std::process::exit(130);
+    }
+    r
+}
+
+/// Restores all registers written while it lives, unless told to keep them.
+pub struct Guard<'a> {
+    h: &'a Handle,
+    t: Duration,
+}
+
+impl<'a> Guard<'a> {
+    pub fn start(h: &'a Handle, t: Duration, keep: bool) -> Option<Guard<'a>> {
+        if keep {
+            return None;
+        }
+        HANDLER.call_once(|| {
+            if let Err(e) = ctrlc::set_handler(on_sigint) {
+                println!("Cannot handle Ctrl-C: {e}");
+            }
+        });
+        *JOURNAL.lock().unwrap() = Some(Vec::new());
+        Some(Guard { h, t })
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        let Some(j) = JOURNAL.lock().unwrap().take() else {
+            return;
+        };
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        if j.is_empty() {
+            return;
+        }
+        println!("Restoring {} registers (use --keep to keep them)", j.len());
+        for (addr, val) in j.into_iter().rev() {
+            if let Err(e) = protocol::write_reg(self.h, self.t, addr, val) {
+                println!("  {addr:08x}: {e}");
+            }
+        }
+    }
+}
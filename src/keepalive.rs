@@ -0,0 +1,49 @@
+use crate::protocol::{self, Handle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// The mask ROM leaves USB mode after a while without requests. The keepalive
+// sends a NOP whenever the device has been idle for the interval. The lock in
+// the handle keeps it from interleaving with foreground transfers. Once the
+// device is gone or stalls the NOP, e.g. after a reset or in U-Boot, it stops.
+
+const POLL: Duration = Duration::from_millis(100);
+
+/// Stops the keepalive thread when dropped.
+pub struct Keepalive {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub fn start(h: Arc<Handle>, t: Duration, interval: Duration) -> Keepalive {
+    let stop = Arc::new(AtomicBool::new(false));
+    let s = stop.clone();
+    let thread = thread::spawn(move || {
+        while !s.load(Ordering::SeqCst) {
+            match protocol::nop_if_idle(&h, t, interval) {
+                Ok(()) => {}
+                Err(e @ (rusb::Error::NoDevice | rusb::Error::Pipe | rusb::Error::Io)) => {
+                    println!("keepalive: nop err: {e:?}, stopping");
+                    break;
+                }
+                Err(e) => println!("keepalive: nop err: {e:?}"),
+            }
+            thread::sleep(POLL.min(interval));
+        }
+    });
+    Keepalive {
+        stop,
+        thread: Some(thread),
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
@@ -1,9 +1,32 @@
-use clap::{Parser, Subcommand, ValueEnum};
-use std::io::Write;
+use clap::{Parser, Subcommand};
+use std::io::{Read, Write};
 use std::time::Duration;
 
+mod adc;
 mod blinky;
+mod board;
+mod boot_info;
+mod caps;
+mod clocks;
+mod efuse;
+mod gdb;
+mod gpio;
+mod i2c;
+mod journal;
+mod keepalive;
+mod next_boot;
+mod pcap;
+mod pinmux;
+mod power;
 mod protocol;
+mod regs;
+mod repl;
+mod scan;
+mod script;
+mod selftest;
+mod soc;
+mod spi_flash;
+mod watchdog;
 
 const USB_VID_AMLOGIC: u16 = 0x1b8e;
 const USB_PID_GX_CHIP: u16 = 0xc003;
@@ -14,55 +37,50 @@ const USB_PID_GADGET: u16 = 0xfada;
 // This is on a TV box based on S905X4
 // const FB_ADDR: u32 = 0x7f80_0000;
 
-#[allow(non_camel_case_types)]
-#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
-enum Board {
-    Khadas_Vim1,
-    LC_A311D_CC,
-    LC_S905D3_CC,
-}
-
-impl std::fmt::Display for Board {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.to_possible_value()
-            .expect("no values are skipped")
-            .get_name()
-            .fmt(f)
-    }
-}
-
 #[derive(Debug, Subcommand)]
 enum Command {
     Nop,
     ChipGen,
-    Info,
+    /// ROM and stage version, password state and supported requests
+    #[clap(verbatim_doc_comment)]
+    Info {
+        /// Probe the supported requests again instead of using the cache
+        #[arg(long)]
+        probe: bool,
+    },
     ChipInfo,
     ChipId,
-    PowerStates,
-    /// Read a 32-bit value from memory
+    /// Decode CPU and power domain states, optionally saved as JSON (GX only)
+    #[clap(verbatim_doc_comment)]
+    PowerStates {
+        /// Also write the states to this file as JSON
+        #[arg(long)]
+        json: Option<String>,
+    },
+    /// Read a 32-bit value from memory (address or register name)
     #[clap(verbatim_doc_comment)]
     ReadMem {
-        #[arg(index = 1, value_parser=clap_num::maybe_hex::<u32>)]
-        address: u32,
+        #[arg(index = 1)]
+        address: String,
 
         #[arg(index = 2, default_value_t = 4)]
         count: u8,
     },
-    /// Write a 32-bit value to memory
+    /// Write a 32-bit value to memory (address or register name)
     #[clap(verbatim_doc_comment)]
     WriteMem {
-        #[arg(index = 1, value_parser=clap_num::maybe_hex::<u32>)]
-        address: u32,
+        #[arg(index = 1)]
+        address: String,
 
         #[arg(index = 2, value_parser=clap_num::maybe_hex::<u32>)]
         value: u32,
     },
-    /// Dump SRAM to file (S905D3 only for now)
+    /// Dump SRAM to file (S905D3 unless --board is given)
     #[clap(verbatim_doc_comment)]
     Dump {
         file_name: String,
     },
-    /// Write file to SRAM (S905D3 only for now; must be multiple of 64 bytes)
+    /// Write file to SRAM (S905D3 unless --board is given; must be multiple of 64 bytes)
     #[clap(verbatim_doc_comment)]
     Write {
         file_name: String,
@@ -73,13 +91,26 @@ enum Command {
         #[arg(index = 1, value_parser=clap_num::maybe_hex::<u32>)]
         address: u32,
     },
-    /// Write file to SRAM and execute (S905D3 only for now, needs header)
+    /// Write file to SRAM and execute (S905D3 unless --board is given, needs header)
     #[clap(verbatim_doc_comment)]
     Run {
         file_name: String,
     },
+    /// Blink the LEDs of a board, see `board list`; identified if not given
+    #[clap(verbatim_doc_comment)]
     Blinky {
-        board: Board,
+        board: Option<String>,
+    },
+    /// Factory smoke test: LEDs, buttons and straps of a board
+    #[clap(verbatim_doc_comment)]
+    Selftest {
+        board: Option<String>,
+    },
+    /// Board profiles: SoC, LEDs, buttons, UART, load address
+    #[clap(verbatim_doc_comment)]
+    Board {
+        #[command(subcommand)]
+        cmd: BoardCommand,
     },
     Shell {
         cmd: String,
@@ -87,25 +118,355 @@ enum Command {
     Tpl {
         cmd: String,
     },
-    Password,
+    /// Unlock a chip that needs a password (64 bytes, raw file or hex on stdin)
+    #[clap(verbatim_doc_comment)]
+    Unlock {
+        #[arg(long)]
+        password_file: Option<String>,
+    },
     Fastboot,
+    /// Try control requests and save how the ROM answers them as JSON
+    /// Resumes a previous scan in the same file. OUT requests that the tool
+    /// knows, e.g. RUN, are skipped unless --known is given.
+    #[clap(verbatim_doc_comment)]
     BruteForceCmds {
         #[arg(index = 1, default_value = "")]
         yolo: String,
+        /// Results file
+        #[arg(short, long, default_value = "scan.json")]
+        output: String,
+        /// Directions, e.g. in,out
+        #[arg(long, value_delimiter = ',', default_value = "in")]
+        dir: Vec<scan::Dir>,
+        /// Requests, e.g. 0x00-0xff
+        #[arg(long, default_value = "0x00-0xff", value_parser = scan::parse_range)]
+        req: scan::Range,
+        /// wValue range
+        #[arg(long, default_value = "0", value_parser = scan::parse_range)]
+        value: scan::Range,
+        /// wIndex range
+        #[arg(long, default_value = "0", value_parser = scan::parse_range)]
+        index: scan::Range,
+        /// Response length for IN, data length (zeros) for OUT
+        #[arg(long, default_value_t = 16, value_parser=clap_num::maybe_hex::<u16>)]
+        len: u16,
+        /// Pause between requests in ms
+        #[arg(long, default_value_t = 500)]
+        delay: u64,
+        /// Also send known OUT requests
+        #[arg(long)]
+        known: bool,
+    },
+    /// Compare two results files of brute-force-cmds
+    #[clap(verbatim_doc_comment)]
+    DiffScans {
+        a: String,
+        b: String,
+    },
+    /// Read-modify-write a 32-bit register (address or register name)
+    #[clap(verbatim_doc_comment)]
+    Modify {
+        address: String,
+        /// Bits to set
+        #[arg(long, default_value_t = 0, value_parser=clap_num::maybe_hex::<u32>)]
+        set: u32,
+        /// Bits to clear
+        #[arg(long, default_value_t = 0, value_parser=clap_num::maybe_hex::<u32>)]
+        clear: u32,
+        /// Bits to toggle
+        #[arg(long, default_value_t = 0, value_parser=clap_num::maybe_hex::<u32>)]
+        toggle: u32,
+        /// Bit field to write, as hi:lo=value (repeatable)
+        #[arg(long)]
+        field: Vec<String>,
+    },
+    /// Named registers, decoded field by field
+    #[clap(verbatim_doc_comment)]
+    Reg {
+        #[command(subcommand)]
+        cmd: RegCommand,
+    },
+    /// GPIO control, e.g. `gpio set GPIOAO_9 low`
+    Gpio {
+        #[command(subcommand)]
+        cmd: GpioCommand,
+    },
+    /// Pin function multiplexing, e.g. `pinmux set GPIOZ_14 eth_link_led`
+    Pinmux {
+        #[command(subcommand)]
+        cmd: PinmuxCommand,
+    },
+    /// I2C access via the Meson I2C controller, e.g. `i2c scan ao`
+    I2c {
+        #[command(subcommand)]
+        cmd: I2cCommand,
+    },
+    /// Tell why the board is in USB mode: reboot reason, boot device, straps
+    #[clap(verbatim_doc_comment)]
+    BootInfo,
+    /// Reset the SoC via the watchdog
+    #[clap(verbatim_doc_comment)]
+    Reset,
+    /// Watchdog control, e.g. `watchdog disable` for long sessions
+    Watchdog {
+        #[command(subcommand)]
+        cmd: WatchdogCommand,
+    },
+    /// Set what the vendor U-Boot boots into after a reset, then reset it
+    /// U-Boot only, the mask ROM ignores this; its boot order is set by straps,
+    /// so there is no eMMC, SD or SPI target.
+    #[clap(verbatim_doc_comment)]
+    SetNextBoot {
+        target: next_boot::Target,
+    },
+    /// Read and decode the eFuse license bits, saving the raw block
+    #[clap(verbatim_doc_comment)]
+    Efuse {
+        /// File for the raw shadow registers
+        #[arg(long, short, default_value = "efuse.bin")]
+        output: String,
+    },
+    /// Print the clock tree with PLL settings and frequencies
+    #[clap(verbatim_doc_comment)]
+    Clocks,
+    /// SAR ADC channels, e.g. `adc read 1`
+    Adc {
+        #[command(subcommand)]
+        cmd: AdcCommand,
+    },
+    /// SPI NOR flash through the SPI flash controller
+    #[clap(verbatim_doc_comment)]
+    SpiFlash {
+        #[command(subcommand)]
+        cmd: SpiFlashCommand,
+    },
+    /// Interactive session with line editing and history
+    #[clap(verbatim_doc_comment)]
+    Repl,
+    /// GDB remote protocol server for memory access, e.g. on :3333
+    #[clap(verbatim_doc_comment)]
+    Gdbserver {
+        #[arg(index = 1, default_value = ":3333")]
+        address: String,
+        /// Architecture of the code on the target, for the PC register
+        #[arg(long, value_enum, default_value_t = gdb::Arch::Aarch64)]
+        arch: gdb::Arch,
+    },
+    /// Run a script of loader operations in one session
+    #[clap(verbatim_doc_comment)]
+    Script {
+        file_name: String,
+    },
+    /// Decode a usbmon capture (pcap or pcapng) as Amlogic protocol traffic
+    #[clap(verbatim_doc_comment)]
+    DecodePcap {
+        file_name: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum RegCommand {
+    /// List known registers, optionally filtered by name
+    List {
+        #[arg(default_value = "")]
+        filter: String,
+    },
+    /// Read a register and decode its fields
+    Get { name: String },
+    /// Write a register: a value, or FIELD=value pairs (read-modify-write)
+    Set {
+        name: String,
+        #[arg(required = true)]
+        values: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum BoardCommand {
+    /// List built-in and user board profiles
+    List,
+    /// Show a board profile
+    Show { name: String },
+    /// Identify the board and its revision by ADC voltages
+    Detect,
+}
+
+#[derive(Debug, Subcommand)]
+enum WatchdogCommand {
+    /// Show whether the watchdog runs, and its count and timeout
+    Status,
+    /// Stop the watchdog
+    Disable,
+    /// Start the watchdog, resetting the SoC after a timeout
+    Arm { ms: u32 },
+}
+
+#[derive(Debug, Subcommand)]
+enum AdcCommand {
+    /// Sample a channel, calibrated to millivolts
+    Read { channel: u8 },
+}
+
+#[derive(Debug, Subcommand)]
+enum GpioCommand {
+    /// Show input level, mode, output level and pull of a pin
+    Get { pin: String },
+    /// Drive a pin low or high, switching it to output
+    Set { pin: String, level: gpio::Level },
+    /// Switch a pin to input or output
+    Mode { pin: String, mode: gpio::Mode },
+    /// Configure the pull resistor of a pin
+    Pull { pin: String, pull: gpio::Pull },
+}
+
+#[derive(Debug, Subcommand)]
+enum I2cCommand {
+    /// Probe all addresses on a bus
+    Scan { bus: String },
+    /// Read bytes from a device register
+    Read {
+        bus: String,
+        #[arg(value_parser=clap_num::maybe_hex::<u8>)]
+        addr: u8,
+        #[arg(value_parser=clap_num::maybe_hex::<u8>)]
+        reg: u8,
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    /// Write bytes to a device register
+    Write {
+        bus: String,
+        #[arg(value_parser=clap_num::maybe_hex::<u8>)]
+        addr: u8,
+        #[arg(value_parser=clap_num::maybe_hex::<u8>)]
+        reg: u8,
+        #[arg(required = true, value_parser=clap_num::maybe_hex::<u8>)]
+        data: Vec<u8>,
     },
 }
 
+#[derive(Debug, Subcommand)]
+enum SpiFlashCommand {
+    /// Read the JEDEC ID
+    Id,
+    /// Read and decode the SFDP tables
+    Sfdp,
+    /// Read flash contents to a file
+    Read {
+        #[arg(value_parser=clap_num::maybe_hex::<u32>)]
+        address: u32,
+        #[arg(value_parser=clap_num::maybe_hex::<u32>)]
+        size: u32,
+        file_name: String,
+    },
+    /// Erase the 4K sectors covering a range
+    Erase {
+        #[arg(value_parser=clap_num::maybe_hex::<u32>)]
+        address: u32,
+        #[arg(value_parser=clap_num::maybe_hex::<u32>)]
+        size: u32,
+    },
+    /// Erase, program and verify a file at a sector aligned address
+    Write {
+        #[arg(value_parser=clap_num::maybe_hex::<u32>)]
+        address: u32,
+        file_name: String,
+        /// Drive the controller register by register instead of running a
+        /// helper from SRAM; much slower
+        #[arg(long)]
+        no_helper: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PinmuxCommand {
+    /// Show the current and available functions of a pin
+    Show { pin: String },
+    /// Switch a pin to a function, or back to `gpio`
+    Set { pin: String, function: String },
+}
+
 /// Amlogic mask ROM loader tool
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// SoC, detected if not given
+    #[arg(long, global = true)]
+    soc: Option<soc::Soc>,
+
+    /// Board profile, for its SoC and load address
+    #[arg(long, global = true)]
+    board: Option<String>,
+
+    /// Send a NOP when idle for this many milliseconds, for long sessions
+    #[arg(long, global = true, value_parser = clap::value_parser!(u64).range(1..))]
+    keepalive: Option<u64>,
+
+    /// Keep register changes of demos instead of restoring them on exit
+    #[arg(long, global = true)]
+    keep: bool,
+
     /// Command to run
     #[command(subcommand)]
     cmd: Command,
 }
 
+/// Read a 64 byte password from a file, or as hex from stdin.
+fn read_password(file_name: Option<&str>) -> Result<[u8; 64], String> {
+    let pw = match file_name {
+        Some(f) => std::fs::read(f).map_err(|e| format!("{f}: {e}"))?,
+        None => {
+            let mut s = String::new();
+            std::io::stdin()
+                .read_to_string(&mut s)
+                .map_err(|e| e.to_string())?;
+            let hex: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+            if !hex.chars().all(|c| c.is_ascii_hexdigit()) || !hex.len().is_multiple_of(2) {
+                return Err("Expected an even number of hex digits".to_string());
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|e| format!("Bad hex: {e}"))?
+        }
+    };
+    pw.try_into()
+        .map_err(|v: Vec<u8>| format!("Password must be 64 bytes, got {}", v.len()))
+}
+
 fn main() {
-    let cmd = Cli::parse().cmd;
+    let cli = Cli::parse();
+    let cmd = cli.cmd;
+    let board = cli.board.map(|b| board::load(&b).unwrap());
+    let cli_soc = cli.soc.or(board.as_ref().map(|b| b.soc));
+
+    // Offline commands, no device needed
+    match (&cmd, cli_soc) {
+        (Command::DecodePcap { file_name }, _) => {
+            pcap::decode(file_name).unwrap();
+            return;
+        }
+        (Command::DiffScans { a, b }, _) => {
+            scan::diff(a, b).unwrap();
+            return;
+        }
+        (Command::Board { cmd }, _) => match cmd {
+            BoardCommand::List => return board::list().unwrap(),
+            BoardCommand::Show { name } => return board::show(&board::load(name).unwrap()),
+            BoardCommand::Detect => {}
+        },
+        (
+            Command::Reg {
+                cmd: RegCommand::List { filter },
+            },
+            Some(soc),
+        ) => {
+            regs::list(&regs::RegDb::load(soc).unwrap(), filter);
+            return;
+        }
+        _ => {}
+    }
 
     println!("Searching for Amlogic USB devices...");
     let dev = rusb::devices()
@@ -145,16 +506,87 @@ fn main() {
     // timeouts per command...
     let timeout = Duration::from_millis(2500);
     let handle = dev.open().expect("Error opening USB device {e:?}");
+    let handle = std::sync::Arc::new(protocol::Handle::new(handle));
+    let _keepalive = cli
+        .keepalive
+        .map(|ms| keepalive::start(handle.clone(), timeout, Duration::from_millis(ms)));
 
-    if let Ok(p) = handle.read_product_string_ascii(&des) {
+    let product = handle.read_product_string_ascii(&des).ok();
+    if let Some(p) = &product {
         println!("Product string: {p}");
     }
 
-    if pid == USB_PID_AML_DNL {
-        protocol::password_test(&handle, timeout);
-        return;
+    let stage = match protocol::stage(&handle, timeout) {
+        Ok(s) => {
+            println!("Stage: {s} ({})", s.what());
+            Some(s)
+        }
+        Err(e) => {
+            println!("Cannot tell the stage: {e}");
+            None
+        }
+    };
+
+    // Only probed for the commands which use them.
+    let reprobe = matches!(cmd, Command::Info { probe: true });
+    let caps = || caps::get_or_unknown(&handle, timeout, reprobe);
+
+    // Everything but identification and unlocking needs an unlocked chip.
+    let needs_unlock = !matches!(
+        cmd,
+        Command::Nop
+            | Command::ChipGen
+            | Command::Info { .. }
+            | Command::ChipInfo
+            | Command::Unlock { .. }
+            | Command::Shell { .. }
+            | Command::Tpl { .. }
+            | Command::Fastboot
+    );
+    if needs_unlock {
+        match protocol::is_locked(&handle, timeout) {
+            Ok(false) => {}
+            Ok(true) => {
+                println!("Chip is locked, run `unlock` first.");
+                return;
+            }
+            Err(e) => {
+                println!("Cannot tell whether the chip is locked: {e}");
+                std::process::exit(1);
+            }
+        }
     }
 
+    let soc = || match cli_soc {
+        Some(s) => s,
+        None => {
+            let s = soc::detect(&handle, timeout).unwrap();
+            println!("Detected SoC: {s}");
+            s
+        }
+    };
+    let reg_db = || regs::RegDb::load(soc()).unwrap();
+    // Plain addresses don't need the register database.
+    let address = |a: &str| match script::parse_u32(a) {
+        Ok(a) => a,
+        Err(_) => regs::resolve(&reg_db(), a).unwrap(),
+    };
+    let load_addr = board
+        .as_ref()
+        .map_or(protocol::S905D3_AHB_SRAM_BASE, |b| b.load_addr);
+    let identify = || {
+        board::identify(&handle, timeout, soc()).map(|(b, r)| {
+            println!("Identified board: {} {}", b.name, r.name);
+            b
+        })
+    };
+    // by argument, else by --board, else by ADC
+    let pick_board = |name: Option<String>| match (name, &board) {
+        (Some(n), _) => board::load(&n),
+        (None, Some(b)) => Ok(b.clone()),
+        (None, None) => identify(),
+    };
+
     match cmd {
         Command::Nop => {
             protocol::nop(&handle, timeout);
@@ -164,12 +596,14 @@ fn main() {
             protocol::chip_gen(&handle, timeout);
             println!();
         }
-        Command::Info => {
+        Command::Info { .. } => {
             println!("\n=======\n");
             protocol::info(&handle, timeout);
+            caps().print();
             println!();
         }
         Command::ChipInfo => {
+            caps().require(protocol::REQ_CHIPINFO).unwrap();
             println!("\n=======\n");
             protocol::chip_info(&handle, timeout);
             println!();
@@ -179,43 +613,46 @@ fn main() {
             protocol::chip_id(&handle, timeout);
             println!();
         }
-        Command::PowerStates => {
+        Command::PowerStates { json } => {
             println!("\n=======\n");
-            protocol::power_states(&handle, timeout);
+            power::print(&handle, timeout, &reg_db(), json.as_deref()).unwrap();
             println!();
         }
-        Command::ReadMem { address, count } => {
+        Command::ReadMem { address: a, count } => {
+            let address = address(&a);
             protocol::read_mem(&handle, timeout, address, count).unwrap();
             // println!("{v:?}");
         }
-        Command::WriteMem { address, value } => {
+        Command::WriteMem { address: a, value } => {
+            let address = address(&a);
             let v = value.to_le().to_ne_bytes();
             println!("{address:x}  {value:x}");
             protocol::write_mem(&handle, timeout, address, &v).unwrap();
         }
         Command::Dump { file_name } => {
-            let addr = protocol::S905D3_AHB_SRAM_BASE;
+            let addr = load_addr;
             let size = 64 * 1024; // 64k
-            let res = protocol::dump(&handle, timeout, addr, size);
+            let res = protocol::dump(&handle, timeout, addr, size).unwrap();
             let mut file = std::fs::OpenOptions::new()
                 .create(true)
                 .write(true)
+                .truncate(true)
                 .open(file_name)
                 .unwrap();
             file.write_all(&res).unwrap();
         }
         Command::Write { file_name } => {
             let file = std::fs::read(file_name).unwrap();
-            let addr = protocol::S905D3_AHB_SRAM_BASE;
-            protocol::write(&handle, timeout, &file, addr);
+            let addr = load_addr;
+            protocol::write(&handle, timeout, &file, addr).unwrap();
         }
         Command::Exec { address } => {
             protocol::exec(&handle, timeout, address).unwrap();
         }
         Command::Run { file_name } => {
             let file = std::fs::read(file_name).unwrap();
-            let addr = protocol::S905D3_AHB_SRAM_BASE;
-            protocol::write(&handle, timeout, &file, addr);
+            let addr = load_addr;
+            protocol::write(&handle, timeout, &file, addr).unwrap();
             protocol::exec(&handle, timeout, addr).unwrap();
         }
         /* TODO
@@ -223,29 +660,201 @@ fn main() {
             protocol::read_mem(&handle, timeout, FB_ADDR, 64).unwrap();
         }
         */
-        Command::Blinky { board } => match board {
-            Board::Khadas_Vim1 => blinky::vim1_blink(&handle, timeout),
-            Board::LC_A311D_CC => blinky::lc_a311d_cc_blink(&handle, timeout),
-            Board::LC_S905D3_CC => blinky::lc_s905d3_cc_blink(&handle, timeout),
-        },
+        Command::Blinky { board } => {
+            let b = pick_board(board).unwrap();
+            journal::run(&handle, timeout, cli.keep, || {
+                blinky::blink(&handle, timeout, &b)
+            })
+            .unwrap();
+        }
+        Command::Selftest { board } => {
+            let b = pick_board(board).unwrap();
+            journal::run(&handle, timeout, cli.keep, || {
+                selftest::run(&handle, timeout, &b)
+            })
+            .unwrap();
+        }
         Command::Shell { cmd } => {
-            protocol::bulk_cmd(&handle, timeout, &cmd);
+            protocol::bulk_cmd(&handle, timeout, stage, &cmd).unwrap();
         }
         Command::Tpl { cmd } => {
-            protocol::tpl_cmd(&handle, timeout, &cmd);
+            protocol::tpl_cmd(&handle, timeout, stage, &cmd).unwrap();
         }
-        Command::Password => {
-            let pw = [0xffu8; 64];
-            protocol::password(&handle, timeout, &pw);
+        Command::Unlock { password_file } => {
+            let pw = read_password(password_file.as_deref()).unwrap();
+            protocol::unlock(&handle, timeout, &pw).unwrap();
         }
         Command::Fastboot => {
-            protocol::tpl_cmd(&handle, timeout, "fastboot");
+            protocol::tpl_cmd(&handle, timeout, stage, "fastboot").unwrap();
         }
-        Command::BruteForceCmds { yolo } => {
+        Command::BruteForceCmds {
+            yolo,
+            output,
+            dir,
+            req,
+            value,
+            index,
+            len,
+            delay,
+            known,
+        } => {
             if !yolo.eq("YOLO") {
                 panic!("Run 'brute-force-cmds YOLO' if you really want this, be careful!");
             }
-            protocol::brute_force_cmds(&handle, timeout);
+            let p = scan::Params {
+                dirs: dir,
+                reqs: req,
+                values: value,
+                indices: index,
+                len,
+                delay: Duration::from_millis(delay),
+                known,
+            };
+            scan::run(&handle, timeout, &p, product, &output).unwrap();
+        }
+        Command::Modify {
+            address: a,
+            set,
+            clear,
+            toggle,
+            field,
+        } => {
+            let address = address(&a);
+            regs::modify(&handle, timeout, address, set, clear, toggle, &field).unwrap();
+        }
+        Command::Reg { cmd } => {
+            let db = reg_db();
+            match cmd {
+                RegCommand::List { filter } => regs::list(&db, &filter),
+                RegCommand::Get { name } => regs::get(&handle, timeout, &db, &name).unwrap(),
+                RegCommand::Set { name, values } => {
+                    regs::set(&handle, timeout, &db, &name, &values).unwrap()
+                }
+            }
+        }
+        Command::Gpio { cmd } => {
+            let soc = soc();
+            let (h, t) = (&handle, timeout);
+            let pin = |p: &str| gpio::pin(soc, p).unwrap();
+            match cmd {
+                GpioCommand::Get { pin: p } => gpio::get(h, t, &pin(&p)),
+                GpioCommand::Set { pin: p, level } => gpio::set(h, t, &pin(&p), level),
+                GpioCommand::Mode { pin: p, mode } => gpio::set_mode(h, t, &pin(&p), mode),
+                GpioCommand::Pull { pin: p, pull } => gpio::set_pull(h, t, &pin(&p), pull),
+            }
+            .unwrap();
+        }
+        Command::I2c { cmd } => {
+            let soc = soc();
+            let init = |b: &str| {
+                let bus = i2c::bus_by_name(soc, b).unwrap();
+                i2c::I2c::init(&handle, timeout, soc, bus).unwrap()
+            };
+            match cmd {
+                I2cCommand::Scan { bus } => i2c::scan(&init(&bus)),
+                I2cCommand::Read {
+                    bus,
+                    addr,
+                    reg,
+                    count,
+                } => init(&bus)
+                    .read(addr, reg, count)
+                    .map(|d| println!("{addr:02x}@{reg:02x}: {d:02x?}")),
+                I2cCommand::Write {
+                    bus,
+                    addr,
+                    reg,
+                    data,
+                } => init(&bus).write(addr, reg, &data),
+            }
+            .unwrap();
+        }
+        Command::SpiFlash { cmd } => {
+            let s = spi_flash::Spifc::init(&handle, timeout, soc()).unwrap();
+            match cmd {
+                SpiFlashCommand::Id => s.jedec_id().map(|id| println!("JEDEC ID: {id:02x?}")),
+                SpiFlashCommand::Sfdp => spi_flash::print_sfdp(&s),
+                SpiFlashCommand::Read {
+                    address,
+                    size,
+                    file_name,
+                } => s
+                    .read(address, size as usize)
+                    .and_then(|d| std::fs::write(file_name, d).map_err(|e| e.to_string())),
+                SpiFlashCommand::Erase { address, size } => s.erase(address, size),
+                SpiFlashCommand::Write {
+                    address,
+                    file_name,
+                    no_helper,
+                } => {
+                    let file = std::fs::read(file_name).unwrap();
+                    match no_helper {
+                        true => spi_flash::flash(&s, address, &file),
+                        false => spi_flash::flash_helper(&s, address, &file),
+                    }
+                }
+            }
+            .unwrap();
+        }
+        Command::Pinmux { cmd } => {
+            let soc = soc();
+            match cmd {
+                PinmuxCommand::Show { pin } => pinmux::show(&handle, timeout, soc, &pin),
+                PinmuxCommand::Set { pin, function } => {
+                    pinmux::set(&handle, timeout, soc, &pin, &function)
+                }
+            }
+            .unwrap();
+        }
+        Command::Repl => {
+            let mode = product.as_deref().unwrap_or("?");
+            repl::run(&handle, timeout, mode).unwrap();
+        }
+        Command::Gdbserver { address, arch } => {
+            gdb::run(&handle, timeout, &address, arch).unwrap();
+        }
+        Command::Script { file_name } => {
+            let src = std::fs::read_to_string(&file_name).unwrap();
+            let steps = script::parse(&src).unwrap();
+            script::run(&handle, timeout, stage, &steps).unwrap();
+        }
+        Command::Board { .. } => {
+            identify().unwrap();
+        }
+        Command::Adc {
+            cmd: AdcCommand::Read { channel },
+        } => {
+            let adc = adc::Adc::init(&handle, timeout, soc()).unwrap();
+            let (raw, mv) = adc.read_mv(channel).unwrap();
+            println!(
+                "ADC channel {channel}: {raw:#x} of {:#x}, {mv} mV",
+                adc.max()
+            );
+        }
+        Command::BootInfo => {
+            boot_info::print(&handle, timeout, &reg_db(), board.as_ref()).unwrap();
+        }
+        Command::Reset => {
+            watchdog::reset(&handle, timeout, soc()).unwrap();
+        }
+        Command::Watchdog { cmd } => {
+            let soc = soc();
+            match cmd {
+                WatchdogCommand::Status => watchdog::status(&handle, timeout, soc),
+                WatchdogCommand::Disable => watchdog::disable(&handle, timeout, soc),
+                WatchdogCommand::Arm { ms } => watchdog::arm(&handle, timeout, soc, ms),
+            }
+            .unwrap();
+        }
+        Command::SetNextBoot { target } => {
+            next_boot::set(&handle, timeout, &reg_db(), target).unwrap();
+        }
+        Command::Efuse { output } => {
+            efuse::report(&handle, timeout, &reg_db(), stage, &output).unwrap();
+        }
+        Command::Clocks => {
+            clocks::print(&handle, timeout, soc()).unwrap();
         }
+        Command::DecodePcap { .. } | Command::DiffScans { .. } => unreachable!(),
     }
 }
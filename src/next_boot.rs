@@ -0,0 +1,40 @@
+use crate::protocol::{self, Handle};
+use crate::regs::RegDb;
+use crate::watchdog;
+use clap::ValueEnum;
+use std::time::Duration;
+
+// Choose what happens on the next reset via the sticky reboot reason. This
+// only works with the vendor U-Boot on the boot device: the mask ROM ignores
+// the reason, U-Boot evaluates it and with "update" enters its USB burning
+// mode, which speaks the same protocol as the mask ROM.
+//
+// Only usb and normal are supported, eMMC, SD and SPI as asked for are not:
+// the ROM boot order comes from the POC straps, and the ROM override behind
+// U-Boot's set_usb_boot_function() lives in closed secure firmware; we know
+// of no register for it.
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// U-Boot's USB burning mode
+    Usb,
+    /// regular boot, clearing a previous request
+    Normal,
+}
+
+// REBOOT_MODE values, see boot_info.rs
+const MODE_NORMAL: u32 = 1;
+const MODE_UPDATE: u32 = 3;
+
+pub fn set(h: &Handle, t: Duration, db: &RegDb, target: Target) -> Result<(), String> {
+    let mode = match target {
+        Target::Usb => MODE_UPDATE,
+        Target::Normal => MODE_NORMAL,
+    };
+    let r = db.get("AO_SEC_SD_CFG15")?;
+    let f = r.field("REBOOT_MODE").ok_or("No REBOOT_MODE field")?;
+    let old = protocol::read_reg(h, t, r.addr)?;
+    protocol::write_reg(h, t, r.addr, f.bits.set(old, mode)?)?;
+    println!("Next boot: {target:?} (reboot mode {mode})");
+    watchdog::reset(h, t, db.soc)
+}
@@ -0,0 +1,690 @@
+use crate::protocol::{self, REQ_TYPE_AMLIN, REQ_TYPE_AMLOUT};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Offline decoder for usbmon captures, as taken with Wireshark or tcpdump on
+// `usbmonN` while running the vendor tools (see adnl-rev.md).
+// Both the classic pcap and the pcapng container formats are supported.
+//
+// usbmon sees the whole bus, so only devices whose device descriptor names
+// an Amlogic VID:PID are decoded. When the enumeration was not captured, the
+// devices sending Amlogic requests are taken instead.
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SHB: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_SPB: u32 = 0x0000_0003;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+
+// see https://www.tcpdump.org/linktypes.html
+const LINKTYPE_USB_LINUX: u16 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u16 = 220;
+
+// struct usbmon_packet from Documentation/usb/usbmon.rst
+const USBMON_HDR_LEN: usize = 48;
+const USBMON_MMAPPED_HDR_LEN: usize = 64;
+
+const URB_SUBMIT: u8 = b'S';
+const URB_COMPLETE: u8 = b'C';
+
+const XFER_CONTROL: u8 = 2;
+const XFER_BULK: u8 = 3;
+
+const EP_DIR_IN: u8 = 0x80;
+
+// GET_DESCRIPTOR for the device descriptor, chapter 9 of the USB spec
+const REQ_TYPE_STD_IN: u8 = 0x80;
+const REQ_GET_DESCRIPTOR: u8 = 0x06;
+const DESC_DEVICE: u16 = 0x0100;
+
+/// A captured frame along with its link type.
+struct Frame<'a> {
+    link_type: u16,
+    data: &'a [u8],
+}
+
+/// The parts of a usbmon header we care about.
+struct Urb<'a> {
+    id: u64,
+    kind: u8,
+    xfer_type: u8,
+    ep: u8,
+    dev: u8,
+    bus: u16,
+    has_setup: bool,
+    setup: [u8; 8],
+    status: i32,
+    length: u32,
+    data: &'a [u8],
+}
+
+/// Endianness-aware reader for the container formats.
+#[derive(Clone, Copy)]
+struct Reader {
+    be: bool,
+}
+
+impl Reader {
+    fn u16(&self, b: &[u8], o: usize) -> u16 {
+        let v = [b[o], b[o + 1]];
+        if self.be {
+            u16::from_be_bytes(v)
+        } else {
+            u16::from_le_bytes(v)
+        }
+    }
+
+    fn u32(&self, b: &[u8], o: usize) -> u32 {
+        let v = b[o..o + 4].try_into().unwrap();
+        if self.be {
+            u32::from_be_bytes(v)
+        } else {
+            u32::from_le_bytes(v)
+        }
+    }
+
+    fn u64(&self, b: &[u8], o: usize) -> u64 {
+        let v = b[o..o + 8].try_into().unwrap();
+        if self.be {
+            u64::from_be_bytes(v)
+        } else {
+            u64::from_le_bytes(v)
+        }
+    }
+}
+
+fn parse_pcap(buf: &[u8]) -> Result<(Reader, Vec<Frame<'_>>), String> {
+    if buf.len() < 24 {
+        return Err("File too short for a pcap header".to_string());
+    }
+    let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    let r = match magic {
+        PCAP_MAGIC | PCAP_MAGIC_NS => Reader { be: false },
+        _ => Reader { be: true },
+    };
+    let link_type = r.u32(buf, 20) as u16;
+    let mut frames = Vec::new();
+    let mut o = 24;
+    while o + 16 <= buf.len() {
+        let cap_len = r.u32(buf, o + 8) as usize;
+        let start = o + 16;
+        let end = start.saturating_add(cap_len);
+        if end > buf.len() {
+            return Err(format!("Truncated record at offset {o:#x}"));
+        }
+        frames.push(Frame {
+            link_type,
+            data: &buf[start..end],
+        });
+        o = end;
+    }
+    Ok((r, frames))
+}
+
+fn parse_pcapng(buf: &[u8]) -> Result<(Reader, Vec<Frame<'_>>), String> {
+    let mut r = Reader { be: false };
+    let mut link_types: Vec<u16> = Vec::new();
+    let mut frames = Vec::new();
+    let mut o = 0;
+    while o + 12 <= buf.len() {
+        let block_type = r.u32(buf, o);
+        if block_type == PCAPNG_SHB {
+            // The byte order magic tells us how to read everything else.
+            let bom = u32::from_le_bytes(buf[o + 8..o + 12].try_into().unwrap());
+            r.be = bom != PCAPNG_BYTE_ORDER_MAGIC;
+            // Interface IDs are per section.
+            link_types.clear();
+        }
+        let len = r.u32(buf, o + 4) as usize;
+        if len < 12 || o + len > buf.len() {
+            return Err(format!("Bad block length {len} at offset {o:#x}"));
+        }
+        let body = &buf[o + 8..o + len - 4];
+        let min_len = match block_type {
+            PCAPNG_IDB => 2,
+            PCAPNG_EPB => 20,
+            PCAPNG_SPB => 4,
+            _ => 0,
+        };
+        if body.len() < min_len {
+            return Err(format!("Truncated block at offset {o:#x}"));
+        }
+        match block_type {
+            PCAPNG_IDB => link_types.push(r.u16(body, 0)),
+            PCAPNG_EPB => {
+                let iface = r.u32(body, 0) as usize;
+                let cap_len = r.u32(body, 12) as usize;
+                let link_type = *link_types
+                    .get(iface)
+                    .ok_or(format!("Unknown interface {iface} at offset {o:#x}"))?;
+                let data = body
+                    .get(20..20 + cap_len)
+                    .ok_or(format!("Truncated packet at offset {o:#x}"))?;
+                frames.push(Frame { link_type, data });
+            }
+            PCAPNG_SPB => {
+                let link_type = *link_types
+                    .first()
+                    .ok_or(format!("No interface for block at offset {o:#x}"))?;
+                let orig_len = r.u32(body, 0) as usize;
+                let cap_len = orig_len.min(body.len() - 4);
+                frames.push(Frame {
+                    link_type,
+                    data: &body[4..4 + cap_len],
+                });
+            }
+            _ => {}
+        }
+        o += len;
+    }
+    Ok((r, frames))
+}
+
+// NOTE: The usbmon header is in host byte order of the capturing machine.
+// We assume that this matches the byte order of the capture file.
+fn parse_urb<'a>(r: Reader, f: &Frame<'a>) -> Option<Urb<'a>> {
+    let hdr_len = match f.link_type {
+        LINKTYPE_USB_LINUX => USBMON_HDR_LEN,
+        LINKTYPE_USB_LINUX_MMAPPED => USBMON_MMAPPED_HDR_LEN,
+        _ => return None,
+    };
+    let b = f.data;
+    if b.len() < hdr_len {
+        return None;
+    }
+    Some(Urb {
+        id: r.u64(b, 0),
+        kind: b[8],
+        xfer_type: b[9],
+        ep: b[10],
+        dev: b[11],
+        bus: r.u16(b, 12),
+        // 0 means the setup packet is present, anything else is a reason why not
+        has_setup: b[14] == 0,
+        setup: b[40..48].try_into().unwrap(),
+        status: r.u32(b, 28) as i32,
+        length: r.u32(b, 32),
+        data: &b[hdr_len..],
+    })
+}
+
+fn words(buf: &[u8]) -> String {
+    let v: Vec<String> = buf
+        .chunks(4)
+        .map(|c| {
+            let mut w = [0u8; 4];
+            w[..c.len()].copy_from_slice(c);
+            format!("{:08x}", u32::from_le_bytes(w))
+        })
+        .collect();
+    format!("[{}]", v.join(", "))
+}
+
+fn c_string(buf: &[u8]) -> String {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).to_string()
+}
+
+fn is_text(buf: &[u8]) -> bool {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    end > 0
+        && buf[..end]
+            .iter()
+            .all(|&b| b.is_ascii_graphic() || b == b' ' || b == b'\n' || b == b'\r')
+}
+
+// ADNL is a fastboot fork; responses start with a 4 character status.
+const FASTBOOT_REPLIES: [&str; 4] = ["OKAY", "FAIL", "INFO", "DATA"];
+
+fn describe_text(buf: &[u8]) -> String {
+    let s = c_string(buf);
+    let s = s.trim_end();
+    match FASTBOOT_REPLIES.iter().find(|r| s.starts_with(*r)) {
+        Some(r) => format!("{r} {:?}", &s[4..]),
+        None => format!("{s:?}"),
+    }
+}
+
+/// Describe a control request from its setup packet and OUT data, if any.
+fn describe_setup(setup: &[u8; 8], data: &[u8]) -> Option<String> {
+    let req_type = setup[0];
+    let req = setup[1];
+    let val = u16::from_le_bytes([setup[2], setup[3]]);
+    let idx = u16::from_le_bytes([setup[4], setup[5]]);
+    let len = u16::from_le_bytes([setup[6], setup[7]]);
+    if req_type != REQ_TYPE_AMLIN && req_type != REQ_TYPE_AMLOUT {
+        return None;
+    }
+    let addr = ((val as u32) << 16) | idx as u32;
+    let s = match (protocol::req_name(req), req) {
+        (Some(n), protocol::REQ_READ_MEM) => format!("{n} {addr:#010x} len {len}"),
+        (Some(n), protocol::REQ_WRITE_MEM) => format!("{n} {addr:#010x} {}", words(data)),
+        (Some(n), protocol::REQ_RUN) => format!("{n} {addr:#010x}"),
+        (Some(n), protocol::REQ_CHIPINFO) => format!("{n} page {idx}"),
+        (Some(n), protocol::REQ_TPL_CMD | protocol::REQ_BULK) => {
+            format!("{n} {:?}", c_string(data))
+        }
+        (Some(n), protocol::REQ_PASSWORD) => format!("{n} {data:02x?}"),
+        (Some(n), _) => format!("{n} value {val:#06x} index {idx:#06x} len {len}"),
+        (None, _) => {
+            format!("REQ_{req:02X}? value {val:#06x} index {idx:#06x} len {len}")
+        }
+    };
+    Some(s)
+}
+
+/// Describe the data returned for an IN control request.
+fn describe_reply(setup: &[u8; 8], data: &[u8]) -> String {
+    match setup[1] {
+        protocol::REQ_IDENTIFY_HOST if data.len() >= 4 => {
+            format!(
+                "ROM {}.{} stage {}.{} {data:02x?}",
+                data[0], data[1], data[2], data[3]
+            )
+        }
+        protocol::REQ_READ_MEM | protocol::REQ_CHIPINFO => words(data),
+        _ => format!("{data:02x?}"),
+    }
+}
+
+fn is_amlogic(vid: u16, pid: u16) -> bool {
+    vid == crate::USB_VID_AMLOGIC
+        && matches!(
+            pid,
+            crate::USB_PID_GX_CHIP | crate::USB_PID_AML_DNL | crate::USB_PID_GADGET
+        )
+}
+
+/// Bus and device addresses of the Amlogic devices, by their device
+/// descriptors, or else by the requests they were sent.
+fn amlogic_devices(urbs: &[Urb]) -> (BTreeSet<(u16, u8)>, bool) {
+    let mut get_desc = BTreeSet::new();
+    let mut devices = BTreeSet::new();
+    for u in urbs.iter().filter(|u| u.xfer_type == XFER_CONTROL) {
+        let s = &u.setup;
+        let val = u16::from_le_bytes([s[2], s[3]]);
+        match u.kind {
+            URB_SUBMIT
+                if u.has_setup
+                    && s[0] == REQ_TYPE_STD_IN
+                    && s[1] == REQ_GET_DESCRIPTOR
+                    && val == DESC_DEVICE =>
+            {
+                get_desc.insert(u.id);
+            }
+            // the first read at address 0 is too short to include the IDs
+            URB_COMPLETE if get_desc.remove(&u.id) && u.data.len() >= 12 => {
+                let vid = u16::from_le_bytes([u.data[8], u.data[9]]);
+                let pid = u16::from_le_bytes([u.data[10], u.data[11]]);
+                if is_amlogic(vid, pid) {
+                    devices.insert((u.bus, u.dev));
+                }
+            }
+            _ => {}
+        }
+    }
+    if !devices.is_empty() {
+        return (devices, true);
+    }
+    let devices = urbs
+        .iter()
+        .filter(|u| u.xfer_type == XFER_CONTROL && u.kind == URB_SUBMIT && u.has_setup)
+        .filter(|u| [REQ_TYPE_AMLIN, REQ_TYPE_AMLOUT].contains(&u.setup[0]))
+        .filter(|u| protocol::req_name(u.setup[1]).is_some())
+        .map(|u| (u.bus, u.dev))
+        .collect();
+    (devices, false)
+}
+
+/// Annotated transcript of the Amlogic traffic in a usbmon capture.
+fn transcript(buf: &[u8]) -> Result<Vec<String>, String> {
+    if buf.len() < 4 {
+        return Err("File too short".to_string());
+    }
+    let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    let (r, frames) = match magic {
+        PCAPNG_SHB => parse_pcapng(buf)?,
+        _ if [PCAP_MAGIC, PCAP_MAGIC_NS].contains(&magic)
+            || [PCAP_MAGIC, PCAP_MAGIC_NS].contains(&magic.swap_bytes()) =>
+        {
+            parse_pcap(buf)?
+        }
+        _ => return Err(format!("Unknown capture format, magic {magic:08x}")),
+    };
+    // frame numbers count from 1, as in Wireshark
+    let (numbers, urbs): (Vec<usize>, Vec<Urb>) = frames
+        .iter()
+        .enumerate()
+        .filter_map(|(n, f)| Some((n + 1, parse_urb(r, f)?)))
+        .unzip();
+
+    let mut lines = Vec::new();
+    let (devices, enumerated) = amlogic_devices(&urbs);
+    let list: Vec<String> = devices
+        .iter()
+        .map(|(bus, dev)| format!("{bus:03}:{dev:03}"))
+        .collect();
+    match enumerated {
+        true => lines.push(format!("Amlogic devices: {}", list.join(", "))),
+        false => lines.push(format!(
+            "No Amlogic device descriptor captured, decoding {}",
+            match list.is_empty() {
+                true => "nothing".to_string(),
+                false => list.join(", "),
+            }
+        )),
+    }
+
+    // Submitted control requests waiting for completion, by URB ID
+    let mut pending: BTreeMap<u64, (usize, [u8; 8], String)> = BTreeMap::new();
+    let mut count = 0;
+    for (n, u) in numbers.into_iter().zip(urbs) {
+        if !devices.contains(&(u.bus, u.dev)) {
+            continue;
+        }
+        let dev = format!("{:03}:{:03}", u.bus, u.dev);
+        match (u.xfer_type, u.kind) {
+            (XFER_CONTROL, URB_SUBMIT) if u.has_setup => {
+                let Some(s) = describe_setup(&u.setup, u.data) else {
+                    continue;
+                };
+                count += 1;
+                // OUT requests are shown when sent, their status follows
+                if u.setup[0] == REQ_TYPE_AMLOUT {
+                    lines.push(format!("#{n:<6} {dev} {s}"));
+                }
+                pending.insert(u.id, (n, u.setup, s));
+            }
+            (XFER_CONTROL, URB_COMPLETE) => {
+                let Some((_, setup, s)) = pending.remove(&u.id) else {
+                    continue;
+                };
+                match (setup[0], u.status) {
+                    (REQ_TYPE_AMLOUT, 0) => {}
+                    (_, 0) => lines.push(format!(
+                        "#{n:<6} {dev} {s} → {}",
+                        describe_reply(&setup, u.data)
+                    )),
+                    (_, e) => lines.push(format!("#{n:<6} {dev} {s} → error {e}")),
+                }
+            }
+            (XFER_BULK, kind) => {
+                let dir_in = u.ep & EP_DIR_IN != 0;
+                // OUT data is in the submission, IN data in the completion.
+                let payload = match (dir_in, kind) {
+                    (false, URB_SUBMIT) | (true, URB_COMPLETE) => u.data,
+                    _ => continue,
+                };
+                if payload.is_empty() {
+                    continue;
+                }
+                count += 1;
+                let arrow = if dir_in { "<-" } else { "->" };
+                let ep = u.ep & !EP_DIR_IN;
+                if is_text(payload) {
+                    lines.push(format!(
+                        "#{n:<6} {dev} {arrow} ep{ep} {}",
+                        describe_text(payload)
+                    ));
+                } else {
+                    lines.push(format!(
+                        "#{n:<6} {dev} {arrow} ep{ep} {} bytes of {}",
+                        payload.len(),
+                        u.length
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    // in capture order
+    let mut pending: Vec<_> = pending
+        .into_values()
+        .filter(|(_, setup, _)| setup[0] == REQ_TYPE_AMLIN)
+        .collect();
+    pending.sort_by_key(|(n, ..)| *n);
+    for (n, setup, s) in pending {
+        let len = u16::from_le_bytes([setup[6], setup[7]]);
+        lines.push(format!("#{n:<6} {s} (len {len}) → no completion captured"));
+    }
+    lines.push(format!(
+        "\n{count} Amlogic requests in {} frames",
+        frames.len()
+    ));
+    Ok(lines)
+}
+
+/// Decode a usbmon capture file and print an annotated transcript.
+pub fn decode(file_name: &str) -> Result<(), String> {
+    let buf = std::fs::read(file_name).map_err(|e| format!("{file_name}: {e}"))?;
+    for l in transcript(&buf)? {
+        println!("{l}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IDENTIFY_HOST, IN, 6 bytes
+    const SETUP: [u8; 8] = [
+        REQ_TYPE_AMLIN,
+        protocol::REQ_IDENTIFY_HOST,
+        0,
+        0,
+        0,
+        0,
+        6,
+        0,
+    ];
+
+    /// A control URB on bus 1; completions carry no setup packet.
+    fn control(id: u64, kind: u8, dev: u8, setup: [u8; 8], status: i32, data: &[u8]) -> Vec<u8> {
+        let mut b = vec![0u8; USBMON_HDR_LEN];
+        b[0..8].copy_from_slice(&id.to_le_bytes());
+        b[8] = kind;
+        b[9] = XFER_CONTROL;
+        b[10] = setup[0] & EP_DIR_IN;
+        b[11] = dev;
+        b[12..14].copy_from_slice(&1u16.to_le_bytes());
+        b[14] = if kind == URB_SUBMIT { 0 } else { b'-' };
+        b[28..32].copy_from_slice(&status.to_le_bytes());
+        b[32..36].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        b[40..48].copy_from_slice(&setup);
+        b.extend(data);
+        b
+    }
+
+    fn usbmon(kind: u8, data: &[u8]) -> Vec<u8> {
+        control(0x1234, kind, 5, SETUP, 0, data)
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend(PCAP_MAGIC.to_le_bytes());
+        b.extend([2, 0, 4, 0]);
+        b.extend([0; 12]);
+        b.extend((LINKTYPE_USB_LINUX as u32).to_le_bytes());
+        for f in frames {
+            b.extend([0; 8]);
+            b.extend((f.len() as u32).to_le_bytes());
+            b.extend((f.len() as u32).to_le_bytes());
+            b.extend(f);
+        }
+        b
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        body.resize(body.len().next_multiple_of(4), 0);
+        let len = (body.len() + 12) as u32;
+        let mut b = Vec::new();
+        b.extend(block_type.to_le_bytes());
+        b.extend(len.to_le_bytes());
+        b.extend(body);
+        b.extend(len.to_le_bytes());
+        b
+    }
+
+    fn pcapng(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut shb = Vec::new();
+        shb.extend(PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend([1, 0, 0, 0]);
+        shb.extend((-1i64).to_le_bytes());
+        let mut idb = Vec::new();
+        idb.extend(LINKTYPE_USB_LINUX.to_le_bytes());
+        idb.extend([0; 6]);
+        let mut b = block(PCAPNG_SHB, &shb);
+        b.extend(block(PCAPNG_IDB, &idb));
+        for f in frames {
+            let mut epb = vec![0; 12];
+            epb.extend((f.len() as u32).to_le_bytes());
+            epb.extend((f.len() as u32).to_le_bytes());
+            epb.extend(f);
+            b.extend(block(PCAPNG_EPB, &epb));
+        }
+        b
+    }
+
+    fn check_identify(r: Reader, frames: &[Frame]) {
+        assert_eq!(frames.len(), 2);
+        let u = parse_urb(r, &frames[0]).unwrap();
+        assert_eq!((u.kind, u.bus, u.dev), (URB_SUBMIT, 1, 5));
+        assert_eq!(
+            describe_setup(&u.setup, u.data).unwrap(),
+            "IDENTIFY_HOST value 0x0000 index 0x0000 len 6"
+        );
+        let u = parse_urb(r, &frames[1]).unwrap();
+        assert_eq!(u.kind, URB_COMPLETE);
+        assert_eq!(
+            describe_reply(&u.setup, u.data),
+            "ROM 0.7 stage 0.0 [00, 07, 00, 00, 01, 00]"
+        );
+    }
+
+    fn identify() -> Vec<Vec<u8>> {
+        vec![
+            usbmon(URB_SUBMIT, &[]),
+            usbmon(URB_COMPLETE, &[0, 7, 0, 0, 1, 0]),
+        ]
+    }
+
+    #[test]
+    fn pcap_frames() {
+        let buf = pcap(&identify());
+        let (r, frames) = parse_pcap(&buf).unwrap();
+        check_identify(r, &frames);
+    }
+
+    #[test]
+    fn pcapng_frames() {
+        let buf = pcapng(&identify());
+        let (r, frames) = parse_pcapng(&buf).unwrap();
+        check_identify(r, &frames);
+    }
+
+    #[test]
+    fn pcap_truncated() {
+        let buf = pcap(&identify());
+        assert!(parse_pcap(&buf[..buf.len() - 1]).is_err());
+        assert!(parse_pcap(&buf[..20]).is_err());
+    }
+
+    #[test]
+    fn pcapng_truncated() {
+        let buf = pcapng(&identify());
+        assert!(parse_pcapng(&buf[..buf.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn pcapng_short_blocks() {
+        let shb = pcapng(&[]);
+        for t in [PCAPNG_IDB, PCAPNG_EPB, PCAPNG_SPB] {
+            let mut buf = shb.clone();
+            buf.extend(block(t, &[]));
+            assert!(parse_pcapng(&buf).is_err());
+        }
+    }
+
+    #[test]
+    fn pcapng_packet_longer_than_block() {
+        let mut buf = pcapng(&[]);
+        let mut epb = vec![0; 12];
+        epb.extend(1000u32.to_le_bytes());
+        epb.extend(1000u32.to_le_bytes());
+        epb.extend([0; 8]);
+        buf.extend(block(PCAPNG_EPB, &epb));
+        assert!(parse_pcapng(&buf).is_err());
+    }
+
+    #[test]
+    fn read_mem_address() {
+        // value and index are the address halves, little endian
+        #[rustfmt::skip]
+        let setup = [REQ_TYPE_AMLIN, protocol::REQ_READ_MEM, 0x00, 0xd9, 0x00, 0xd4, 12, 0];
+        assert_eq!(
+            describe_setup(&setup, &[]).unwrap(),
+            "READ_MEM 0xd900d400 len 12"
+        );
+    }
+
+    // Enumeration of a GXL in USB boot mode next to a mouse, whose vendor
+    // request reuses an Amlogic request number, then a failed WRITE_MEM.
+    #[rustfmt::skip]
+    fn capture() -> Vec<Vec<u8>> {
+        let get_desc = |len| [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, len, 0x00];
+        let mouse = [REQ_TYPE_AMLIN, protocol::REQ_READ_MEM, 0, 0, 0, 0, 4, 0];
+        let write = [REQ_TYPE_AMLOUT, protocol::REQ_WRITE_MEM, 0x00, 0xc8, 0x24, 0x00, 4, 0];
+        let run = [REQ_TYPE_AMLOUT, protocol::REQ_RUN, 0xfa, 0xff, 0x00, 0x00, 0, 0];
+        let desc = [
+            0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40,
+            0x8e, 0x1b, 0x03, 0xc0, 0x20, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ];
+        vec![
+            control(1, URB_SUBMIT, 0, get_desc(0x40), 0, &[]),
+            control(1, URB_COMPLETE, 0, [0; 8], 0, &desc[..8]),
+            control(2, URB_SUBMIT, 9, get_desc(0x12), 0, &[]),
+            control(2, URB_COMPLETE, 9, [0; 8], 0, &desc),
+            control(3, URB_SUBMIT, 3, mouse, 0, &[]),
+            control(3, URB_COMPLETE, 3, [0; 8], 0, &[1, 2, 3, 4]),
+            control(4, URB_SUBMIT, 9, SETUP, 0, &[]),
+            control(4, URB_COMPLETE, 9, [0; 8], 0, &[0, 9, 0, 0, 1, 0]),
+            control(5, URB_SUBMIT, 9, write, 0, &[0xff, 0x3d, 0xff, 0xbf]),
+            control(5, URB_COMPLETE, 9, [0; 8], -32, &[]),
+            control(6, URB_SUBMIT, 9, run, 0, &[]),
+            control(6, URB_COMPLETE, 9, [0; 8], 0, &[]),
+        ]
+    }
+
+    #[test]
+    fn transcript_of_amlogic_device() {
+        let lines = transcript(&pcapng(&capture())).unwrap();
+        assert_eq!(
+            lines,
+            [
+                "Amlogic devices: 001:009",
+                "#8      001:009 IDENTIFY_HOST value 0x0000 index 0x0000 len 6 → ROM 0.9 stage 0.0 [00, 09, 00, 00, 01, 00]",
+                "#9      001:009 WRITE_MEM 0xc8000024 [bfff3dff]",
+                "#10     001:009 WRITE_MEM 0xc8000024 [bfff3dff] → error -32",
+                "#11     001:009 RUN 0xfffa0000",
+                "\n3 Amlogic requests in 12 frames",
+            ]
+        );
+    }
+
+    #[test]
+    fn out_error_without_enumeration() {
+        let lines = transcript(&pcap(&capture()[8..10])).unwrap();
+        assert_eq!(
+            lines,
+            [
+                "No Amlogic device descriptor captured, decoding 001:009",
+                "#1      001:009 WRITE_MEM 0xc8000024 [bfff3dff]",
+                "#2      001:009 WRITE_MEM 0xc8000024 [bfff3dff] → error -32",
+                "\n1 Amlogic requests in 2 frames",
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use crate::gpio::{self, Domain, Pin};
+use crate::protocol::{self, Handle};
+use crate::soc::Soc;
+use std::time::Duration;
+
+// Pin function multiplexing, after Linux drivers/pinctrl/meson/
+//
+// GX SoCs have one enable bit per function group in the mux registers; a pin
+// is a GPIO when none of the groups it is part of are enabled. Groups missing
+// from the tables can't be told apart, so a set bit the tables don't know in
+// the pin's mux registers makes its function unknown rather than GPIO.
+// G12A and later have a 4-bit function number per pin, 0 meaning GPIO.
+//
+// The tables only cover functions that are useful from the loader so far.
+
+const GX_AO_MUX: u32 = 0xc810_0014;
+const GX_EE_MUX: u32 = 0xc883_44b0;
+const G12_AO_MUX: u32 = 0xff80_0014;
+const G12_EE_MUX: u32 = 0xff63_46c0;
+
+pub const GPIO: &str = "gpio";
+
+/// Function group on GX: pins and the mux register index and bit enabling it
+struct Group {
+    name: &'static str,
+    pins: &'static [&'static str],
+    reg: u8,
+    bit: u8,
+}
+
+const fn group(name: &'static str, pins: &'static [&'static str], reg: u8, bit: u8) -> Group {
+    Group {
+        name,
+        pins,
+        reg,
+        bit,
+    }
+}
+
+/// Function on G12: pin, function name and its number
+struct Func {
+    pin: &'static str,
+    name: &'static str,
+    value: u8,
+}
+
+const fn func(pin: &'static str, name: &'static str, value: u8) -> Func {
+    Func { pin, name, value }
+}
+
+const EMMC_D: &[&str] = &[
+    "BOOT_0", "BOOT_1", "BOOT_2", "BOOT_3", "BOOT_4", "BOOT_5", "BOOT_6", "BOOT_7",
+];
+
+#[rustfmt::skip]
+const GXBB_AO_GROUPS: [Group; 4] = [
+    group("uart_tx_ao_a", &["GPIOAO_0"], 0, 12),
+    group("uart_rx_ao_a", &["GPIOAO_1"], 0, 11),
+    group("i2c_sck_ao",   &["GPIOAO_4"], 0, 6),
+    group("i2c_sda_ao",   &["GPIOAO_5"], 0, 5),
+];
+
+#[rustfmt::skip]
+const GXBB_EE_GROUPS: [Group; 5] = [
+    group("emmc_nand_d07", EMMC_D,       4, 30),
+    group("emmc_clk",      &["BOOT_8"],  4, 18),
+    group("emmc_cmd",      &["BOOT_10"], 4, 19),
+    group("uart_tx_a",     &["GPIOX_12"], 4, 13),
+    group("uart_rx_a",     &["GPIOX_13"], 4, 12),
+];
+
+#[rustfmt::skip]
+const GXL_AO_GROUPS: [Group; 6] = [
+    group("uart_tx_ao_a",    &["GPIOAO_0"], 0, 12),
+    group("uart_rx_ao_a",    &["GPIOAO_1"], 0, 11),
+    group("i2c_sck_ao",      &["GPIOAO_4"], 0, 6),
+    group("i2c_sda_ao",      &["GPIOAO_5"], 0, 5),
+    group("remote_input_ao", &["GPIOAO_7"], 0, 0),
+    group("pwm_ao_b",        &["GPIOAO_9"], 0, 3),
+];
+
+#[rustfmt::skip]
+const GXL_EE_GROUPS: [Group; 29] = [
+    group("eth_link_led",  &["GPIOZ_14"], 4, 25),
+    group("eth_act_led",   &["GPIOZ_15"], 4, 24),
+    group("sdio_d0",       &["GPIOX_0"],  5, 31),
+    group("sdio_d1",       &["GPIOX_1"],  5, 30),
+    group("sdio_d2",       &["GPIOX_2"],  5, 29),
+    group("sdio_d3",       &["GPIOX_3"],  5, 28),
+    group("sdio_clk",      &["GPIOX_4"],  5, 27),
+    group("sdio_cmd",      &["GPIOX_5"],  5, 26),
+    group("uart_tx_a",     &["GPIOX_12"], 5, 19),
+    group("uart_rx_a",     &["GPIOX_13"], 5, 18),
+    group("sdcard_d0",     &["CARD_1"],   6, 5),
+    group("sdcard_d1",     &["CARD_0"],   6, 4),
+    group("sdcard_d2",     &["CARD_5"],   6, 3),
+    group("sdcard_d3",     &["CARD_4"],   6, 2),
+    group("sdcard_clk",    &["CARD_2"],   6, 1),
+    group("sdcard_cmd",    &["CARD_3"],   6, 0),
+    group("emmc_nand_d07", EMMC_D,        7, 31),
+    group("emmc_clk",      &["BOOT_8"],   7, 30),
+    group("emmc_cmd",      &["BOOT_10"],  7, 29),
+    group("nor_d",         &["BOOT_11"],  7, 13),
+    group("nor_q",         &["BOOT_12"],  7, 12),
+    group("nor_c",         &["BOOT_13"],  7, 11),
+    group("nor_cs",        &["BOOT_15"],  7, 10),
+    group("i2c_sda_a",     &["GPIODV_24"], 1, 15),
+    group("i2c_sck_a",     &["GPIODV_25"], 1, 14),
+    group("i2c_sda_b",     &["GPIODV_26"], 1, 13),
+    group("i2c_sck_b",     &["GPIODV_27"], 1, 12),
+    group("i2c_sda_c",     &["GPIODV_18"], 1, 11),
+    group("i2c_sck_c",     &["GPIODV_19"], 1, 10),
+];
+
+// bank, mux register index and bit of the first pin
+#[rustfmt::skip]
+const G12_PMX_BANKS: [(&str, u8, u8); 8] = [
+    ("GPIOZ",  0x6, 0),
+    ("GPIOH",  0xb, 0),
+    ("BOOT",   0x0, 0),
+    ("GPIOC",  0x9, 0),
+    ("GPIOA",  0xd, 0),
+    ("GPIOX",  0x3, 0),
+    ("GPIOAO", 0x0, 0),
+    ("GPIOE",  0x1, 16),
+];
+
+#[rustfmt::skip]
+const G12_FUNCS: [Func; 41] = [
+    func("GPIOAO_0",  "uart_ao_a_tx", 1),
+    func("GPIOAO_1",  "uart_ao_a_rx", 1),
+    func("GPIOAO_2",  "i2c_ao_sck",   1),
+    func("GPIOAO_3",  "i2c_ao_sda",   1),
+    func("GPIOZ_14",  "eth_link_led", 1),
+    func("GPIOZ_15",  "eth_act_led",  1),
+    func("GPIOX_0",   "sdio_d0",      1),
+    func("GPIOX_1",   "sdio_d1",      1),
+    func("GPIOX_2",   "sdio_d2",      1),
+    func("GPIOX_3",   "sdio_d3",      1),
+    func("GPIOX_4",   "sdio_clk",     1),
+    func("GPIOX_5",   "sdio_cmd",     1),
+    func("GPIOX_12",  "uart_a_tx",    1),
+    func("GPIOX_13",  "uart_a_rx",    1),
+    func("GPIOC_0",   "sdcard_d0",    1),
+    func("GPIOC_1",   "sdcard_d1",    1),
+    func("GPIOC_2",   "sdcard_d2",    1),
+    func("GPIOC_3",   "sdcard_d3",    1),
+    func("GPIOC_4",   "sdcard_clk",   1),
+    func("GPIOC_5",   "sdcard_cmd",   1),
+    func("BOOT_0",    "emmc_d0",      1),
+    func("BOOT_1",    "emmc_d1",      1),
+    func("BOOT_2",    "emmc_d2",      1),
+    func("BOOT_3",    "emmc_d3",      1),
+    func("BOOT_4",    "emmc_d4",      1),
+    func("BOOT_5",    "emmc_d5",      1),
+    func("BOOT_6",    "emmc_d6",      1),
+    func("BOOT_7",    "emmc_d7",      1),
+    func("BOOT_8",    "emmc_clk",     1),
+    func("BOOT_10",   "emmc_cmd",     1),
+    func("BOOT_13",   "emmc_ds",      1),
+    func("BOOT_3",    "nor_hold",     3),
+    func("BOOT_4",    "nor_d",        3),
+    func("BOOT_5",    "nor_q",        3),
+    func("BOOT_6",    "nor_c",        3),
+    func("BOOT_7",    "nor_wp",       3),
+    func("BOOT_14",   "nor_cs",       3),
+    func("GPIOA_14",  "i2c3_sda_a",   2),
+    func("GPIOA_15",  "i2c3_sck_a",   2),
+    func("GPIOH_6",   "i2c3_sda_h",   2),
+    func("GPIOH_7",   "i2c3_sck_h",   2),
+];
+
+fn gx_groups(soc: Soc, domain: Domain) -> Vec<&'static Group> {
+    let (ao, ee): (&[Group], &[Group]) = match soc {
+        Soc::Gxbb => (&GXBB_AO_GROUPS, &GXBB_EE_GROUPS),
+        _ => (&GXL_AO_GROUPS, &GXL_EE_GROUPS),
+    };
+    match domain {
+        Domain::Ao => ao.iter().collect(),
+        Domain::Ee => ee.iter().collect(),
+    }
+}
+
+fn mux_base(soc: Soc, domain: Domain) -> u32 {
+    match (soc.is_g12(), domain) {
+        (false, Domain::Ao) => GX_AO_MUX,
+        (false, Domain::Ee) => GX_EE_MUX,
+        (true, Domain::Ao) => G12_AO_MUX,
+        (true, Domain::Ee) => G12_EE_MUX,
+    }
+}
+
+/// Mux register address and bit offset of a pin's function number on G12
+fn g12_field(p: &Pin) -> Result<(u32, u32), String> {
+    let (_, reg, bit) = G12_PMX_BANKS
+        .iter()
+        .find(|(b, _, _)| *b == p.bank.name)
+        .ok_or(format!("No mux bank for {p}"))?;
+    let offs = *bit as u32 + 4 * p.n as u32;
+    let addr = mux_base(Soc::G12a, p.bank.domain) + 4 * (*reg as u32 + offs / 32);
+    Ok((addr, offs % 32))
+}
+
+/// Names of the functions a pin can have, besides GPIO
+pub fn functions(soc: Soc, p: &Pin) -> Vec<&'static str> {
+    let name = p.to_string();
+    if soc.is_g12() {
+        G12_FUNCS
+            .iter()
+            .filter(|f| f.pin == name)
+            .map(|f| f.name)
+            .collect()
+    } else {
+        gx_groups(soc, p.bank.domain)
+            .into_iter()
+            .filter(|g| g.pins.contains(&name.as_str()))
+            .map(|g| g.name)
+            .collect()
+    }
+}
+
+/// Read the current function of a pin.
+pub fn current(h: &Handle, t: Duration, soc: Soc, p: &Pin) -> Result<String, String> {
+    let name = p.to_string();
+    if soc.is_g12() {
+        let (addr, bit) = g12_field(p)?;
+        let v = (protocol::read_reg(h, t, addr)? >> bit) & 0xf;
+        if v == 0 {
+            return Ok(GPIO.to_string());
+        }
+        let f = G12_FUNCS
+            .iter()
+            .find(|f| f.pin == name && f.value as u32 == v);
+        return Ok(match f {
+            Some(f) => f.name.to_string(),
+            None => format!("function {v}"),
+        });
+    }
+    let base = mux_base(soc, p.bank.domain);
+    let groups = gx_groups(soc, p.bank.domain);
+    let own: Vec<&&Group> = groups
+        .iter()
+        .filter(|g| g.pins.contains(&name.as_str()))
+        .collect();
+    // the registers of the pin's groups, or all of them for untabled pins
+    let mut regs: Vec<u8> = match own.is_empty() {
+        true => groups.iter().map(|g| g.reg).collect(),
+        false => own.iter().map(|g| g.reg).collect(),
+    };
+    regs.sort();
+    regs.dedup();
+    let mut enabled = Vec::new();
+    let mut unknown = Vec::new();
+    for reg in regs {
+        let v = protocol::read_reg(h, t, base + 4 * reg as u32)?;
+        enabled.extend(
+            own.iter()
+                .filter(|g| g.reg == reg && v & (1 << g.bit) != 0)
+                .map(|g| g.name),
+        );
+        for bit in (0..32).filter(|b| v & (1 << b) != 0) {
+            if !groups.iter().any(|g| g.reg == reg && g.bit == bit) {
+                unknown.push(format!("{reg}/{bit}"));
+            }
+        }
+    }
+    Ok(match (enabled.len(), unknown.len()) {
+        (0, 0) => GPIO.to_string(),
+        (0, _) => format!("unknown ({} set)", unknown.join(", ")),
+        // more than one is a conflict, which the hardware resolves somehow
+        _ => enabled.join(" + "),
+    })
+}
+
+pub fn show(h: &Handle, t: Duration, soc: Soc, pin: &str) -> Result<(), String> {
+    let p = gpio::pin(soc, pin)?;
+    let cur = current(h, t, soc, &p)?;
+    println!("{p}: {cur}");
+    println!("  available: {GPIO} {}", functions(soc, &p).join(" "));
+    Ok(())
+}
+
+/// Switch a pin to a function, or back to GPIO.
+///
+/// On GX, other groups on the pin are disabled first so that there is never
+/// more than one function enabled. This also affects the other pins of those
+/// groups, so we tell about it.
+pub fn set(h: &Handle, t: Duration, soc: Soc, pin: &str, function: &str) -> Result<(), String> {
+    let p = gpio::pin(soc, pin)?;
+    let name = p.to_string();
+    let avail = functions(soc, &p);
+    if function != GPIO && !avail.contains(&function) {
+        return Err(format!(
+            "{p} has no function {function}, available: {GPIO} {}",
+            avail.join(" ")
+        ));
+    }
+    if soc.is_g12() {
+        let (addr, bit) = g12_field(&p)?;
+        let v = match G12_FUNCS
+            .iter()
+            .find(|f| f.pin == name && f.name == function)
+        {
+            Some(f) => f.value as u32,
+            None => 0,
+        };
+        protocol::modify_reg(h, t, addr, 0xf << bit, v << bit, 0)?;
+    } else {
+        let base = mux_base(soc, p.bank.domain);
+        let groups: Vec<&Group> = gx_groups(soc, p.bank.domain)
+            .into_iter()
+            .filter(|g| g.pins.contains(&name.as_str()))
+            .collect();
+        for g in groups.iter().filter(|g| g.name != function) {
+            let addr = base + 4 * g.reg as u32;
+            let (old, _) = protocol::modify_reg(h, t, addr, 1 << g.bit, 0, 0)?;
+            if old & (1 << g.bit) != 0 && g.pins.len() > 1 {
+                println!("Disabled {} on {}", g.name, g.pins.join(", "));
+            }
+        }
+        if let Some(g) = groups.iter().find(|g| g.name == function) {
+            let addr = base + 4 * g.reg as u32;
+            protocol::modify_reg(h, t, addr, 0, 1 << g.bit, 0)?;
+        }
+    }
+    println!("{p}: {}", current(h, t, soc, &p)?);
+    Ok(())
+}
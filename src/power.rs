@@ -0,0 +1,100 @@
+use crate::protocol::{self, Handle};
+use crate::regs::RegDb;
+use serde::Serialize;
+use std::time::Duration;
+
+// CPU and domain power states, decoded with the register database
+//
+// GX only: G12 switches the cores in secure firmware, and without its CPU
+// power and clamp registers half a report would mislead.
+
+const GX_REGS: [&str; 8] = [
+    "AO_RTI_PWR_A53_CNTL0",
+    "AO_RTI_PWR_A53_CNTL1",
+    "AO_RTI_PWR_A53_MEM_PD0",
+    "AO_RTI_GEN_PWR_SLEEP0",
+    "AO_RTI_GEN_PWR_ISO0",
+    "HHI_MEM_PD_REG0",
+    "HHI_VPU_MEM_PD_REG0",
+    "HHI_VPU_MEM_PD_REG1",
+];
+
+#[derive(Serialize)]
+struct FieldState {
+    name: String,
+    bits: String,
+    value: u32,
+    desc: String,
+}
+
+#[derive(Serialize)]
+struct RegState {
+    name: String,
+    addr: u32,
+    value: u32,
+    desc: String,
+    fields: Vec<FieldState>,
+}
+
+#[derive(Serialize)]
+struct PowerStates {
+    soc: String,
+    registers: Vec<RegState>,
+}
+
+fn read(h: &Handle, t: Duration, db: &RegDb) -> Result<PowerStates, String> {
+    if db.soc.is_g12() {
+        return Err(format!(
+            "Power states are decoded on GX only, not {}",
+            db.soc
+        ));
+    }
+    let mut registers = Vec::new();
+    for n in GX_REGS {
+        let r = db.get(n)?;
+        let value = protocol::read_reg(h, t, r.addr)?;
+        let fields = r
+            .fields
+            .iter()
+            .map(|f| FieldState {
+                name: f.name.clone(),
+                bits: f.bits.to_string(),
+                value: f.bits.get(value),
+                desc: f.desc.clone(),
+            })
+            .collect();
+        registers.push(RegState {
+            name: r.name.clone(),
+            addr: r.addr,
+            value,
+            desc: r.desc.clone(),
+            fields,
+        });
+    }
+    Ok(PowerStates {
+        soc: db.soc.to_string(),
+        registers,
+    })
+}
+
+/// Print the power state registers as a table, and optionally save them as
+/// JSON.
+pub fn print(h: &Handle, t: Duration, db: &RegDb, json: Option<&str>) -> Result<(), String> {
+    let s = read(h, t, db)?;
+    println!("Power states on {}:", s.soc);
+    for r in &s.registers {
+        println!(
+            "{:<24} @{:08x} = {:08x}  {}",
+            r.name, r.addr, r.value, r.desc
+        );
+        for f in &r.fields {
+            let bits = format!("[{}]", f.bits);
+            println!("  {:<16} {bits:<8} = {:#x}  {}", f.name, f.value, f.desc);
+        }
+    }
+    if let Some(file_name) = json {
+        let j = serde_json::to_string_pretty(&s).map_err(|e| e.to_string())?;
+        std::fs::write(file_name, j + "\n").map_err(|e| format!("{file_name}: {e}"))?;
+    }
+    Ok(())
+}
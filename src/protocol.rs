@@ -1,7 +1,87 @@
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// USB device handle
+///
+/// Transfers are serialized through a lock, which also tracks when the device
+/// was last talked to, so that the keepalive never interleaves with them.
+pub struct Handle {
+    dev: rusb::DeviceHandle<rusb::GlobalContext>,
+    last: Mutex<Instant>,
+    /// first bulk IN endpoint, where U-Boot's burning gadget answers
+    bulk_in: Option<u8>,
+}
+
+fn find_bulk_in(dev: &rusb::Device<rusb::GlobalContext>) -> Option<(u8, u8)> {
+    let config = dev.active_config_descriptor().ok()?;
+    config
+        .interfaces()
+        .flat_map(|i| i.descriptors())
+        .find_map(|d| {
+            d.endpoint_descriptors()
+                .find(|e| {
+                    e.direction() == rusb::Direction::In
+                        && e.transfer_type() == rusb::TransferType::Bulk
+                })
+                .map(|e| (d.interface_number(), e.address()))
+        })
+}
+
+impl Handle {
+    pub fn new(mut dev: rusb::DeviceHandle<rusb::GlobalContext>) -> Self {
+        // Claimed up front, U-Boot answers bulk commands there.
+        let bulk_in = find_bulk_in(&dev.device())
+            .and_then(|(iface, ep)| dev.claim_interface(iface).ok().map(|_| ep));
+        Handle {
+            dev,
+            last: Mutex::new(Instant::now()),
+            bulk_in,
+        }
+    }
+
+    pub fn read_control(
+        &self,
+        req_type: u8,
+        req: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        t: Duration,
+    ) -> rusb::Result<usize> {
+        let mut last = self.last.lock().unwrap();
+        let r = self.dev.read_control(req_type, req, value, index, buf, t);
+        *last = Instant::now();
+        r
+    }
 
-// keeping it short :)
-pub type Handle = rusb::DeviceHandle<rusb::GlobalContext>;
+    pub fn write_control(
+        &self,
+        req_type: u8,
+        req: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        t: Duration,
+    ) -> rusb::Result<usize> {
+        let mut last = self.last.lock().unwrap();
+        let r = self.dev.write_control(req_type, req, value, index, buf, t);
+        *last = Instant::now();
+        r
+    }
+
+    /// Read from the bulk IN endpoint, e.g. the reply to a bulk command.
+    pub fn read_bulk(&self, buf: &mut [u8], t: Duration) -> rusb::Result<usize> {
+        let ep = self.bulk_in.ok_or(rusb::Error::NotFound)?;
+        let mut last = self.last.lock().unwrap();
+        let r = self.dev.read_bulk(ep, buf, t);
+        *last = Instant::now();
+        r
+    }
+
+    pub fn read_product_string_ascii(&self, des: &rusb::DeviceDescriptor) -> rusb::Result<String> {
+        self.dev.read_product_string_ascii(des)
+    }
+}
 
 const DEBUG: bool = false;
 
@@ -13,8 +93,6 @@ const DEBUG: bool = false;
 
 // from Khadas tools / update (objdump is your friend :))
 const S905X_CHIP_ID_ADDR: u32 = 0xd900_d400;
-// from S905X manual, p47
-const S905X_CPU_POWER_STATE: u32 = 0xc810_00e0;
 
 // these are also taken from khadas update tool
 // const X_ADDR3: u32 = 0xfffc_d400;
@@ -28,27 +106,57 @@ pub const S905D3_AHB_SRAM_BASE: u32 = 0xfffa_0000;
 /* Request types - just one per direction */
 // see https://vovkos.github.io/doxyrest/samples/libusb-sphinxdoc/enum_libusb_endpoint_direction.html#doxid-group-libusb-desc-1ga86c880af878493aa8f805c2aba654b8b
 // IN
-const REQ_TYPE_AMLIN: u8 = 0xc0;
+pub const REQ_TYPE_AMLIN: u8 = 0xc0;
 // OUT
-const REQ_TYPE_AMLOUT: u8 = 0x40;
+pub const REQ_TYPE_AMLOUT: u8 = 0x40;
 
 // NOTE: Any non-existent commands works for this, as it seems.
-const REQ_CHIP_GEN: u8 = 0x12;
+pub const REQ_CHIP_GEN: u8 = 0x12;
 
 /* Actual commands */
-const REQ_WRITE_MEM: u8 = 0x01;
-const REQ_READ_MEM: u8 = 0x02;
+pub const REQ_WRITE_MEM: u8 = 0x01;
+pub const REQ_READ_MEM: u8 = 0x02;
 
-const REQ_RUN: u8 = 0x05;
+pub const REQ_RUN: u8 = 0x05;
 
-const REQ_IDENTIFY_HOST: u8 = 0x20;
+pub const REQ_IDENTIFY_HOST: u8 = 0x20;
 // NOTE: This appears to not exist on the S905X, so it behaves as REQ_CHIP_GEN.
-const REQ_CHIPINFO: u8 = 0x40;
+pub const REQ_CHIPINFO: u8 = 0x40;
+
+pub const REQ_TPL_CMD: u8 = 0x30;
+pub const REQ_BULK: u8 = 0x34;
+pub const REQ_PASSWORD: u8 = 0x35;
+pub const REQ_NOP: u8 = 0x36;
+
+/// Human readable name of a request, e.g. for decoding captured traffic.
+pub fn req_name(req: u8) -> Option<&'static str> {
+    match req {
+        REQ_WRITE_MEM => Some("WRITE_MEM"),
+        REQ_READ_MEM => Some("READ_MEM"),
+        REQ_RUN => Some("RUN"),
+        REQ_CHIP_GEN => Some("CHIP_GEN"),
+        REQ_IDENTIFY_HOST => Some("IDENTIFY_HOST"),
+        REQ_TPL_CMD => Some("TPL_CMD"),
+        REQ_BULK => Some("BULK"),
+        REQ_PASSWORD => Some("PASSWORD"),
+        REQ_NOP => Some("NOP"),
+        REQ_CHIPINFO => Some("CHIPINFO"),
+        _ => None,
+    }
+}
 
-const REQ_TPL_CMD: u8 = 0x30;
-const REQ_BULK: u8 = 0x34;
-const REQ_PASSWORD: u8 = 0x35;
-const REQ_NOP: u8 = 0x36;
+/// Send a NOP only if the device has been idle for a while.
+pub fn nop_if_idle(h: &Handle, t: Duration, idle: Duration) -> Result<(), rusb::Error> {
+    let mut last = h.last.lock().unwrap();
+    if last.elapsed() < idle {
+        return Ok(());
+    }
+    let r = h
+        .dev
+        .write_control(REQ_TYPE_AMLOUT, REQ_NOP, 0x0, 0x0, &[], t);
+    *last = Instant::now();
+    r.map(|_| ())
+}
 
 // whatever nop does, useful for testing communication
 pub fn nop(h: &Handle, t: Duration) {
@@ -105,18 +213,96 @@ fn int_to_bool_str(v: u8) -> &'static str {
     }
 }
 
-pub fn info(h: &Handle, t: Duration) {
-    println!("Read chip information\n");
+/// Get ROM version, stage version, and password flags.
+pub fn identify_host(h: &Handle, t: Duration) -> Result<[u8; 6], String> {
     let mut buf = [0u8; 6];
     match h.read_control(REQ_TYPE_AMLIN, REQ_IDENTIFY_HOST, 0x0, 0x0, &mut buf, t) {
-        Ok(_) => {
+        Ok(_) => Ok(buf),
+        Err(e) => Err(format!("identify_host err: {e:?}")),
+    }
+}
+
+/// Boot stage answering on the USB device
+///
+/// The mask ROM and U-Boot's USB burning gadget share the same IDs, but the
+/// stage version tells them apart; the vendor tool's `identify` shows 0-0 in
+/// the ROM, 0-8 once BL2 runs and 0-16 in U-Boot (TPL).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Bl1,
+    Bl2,
+    Tpl,
+    Unknown(u8, u8),
+}
+
+impl Stage {
+    pub fn from_identify(id: &[u8; 6]) -> Self {
+        match (id[2], id[3]) {
+            (0, 0) => Self::Bl1,
+            (0, 8) => Self::Bl2,
+            (0, 16) => Self::Tpl,
+            (a, b) => Self::Unknown(a, b),
+        }
+    }
+
+    pub fn what(&self) -> &'static str {
+        match self {
+            Self::Bl1 => "mask ROM",
+            Self::Bl2 => "SPL",
+            Self::Tpl => "U-Boot",
+            Self::Unknown(..) => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bl1 => write!(f, "BL1"),
+            Self::Bl2 => write!(f, "BL2"),
+            Self::Tpl => write!(f, "TPL"),
+            Self::Unknown(a, b) => write!(f, "stage {a}.{b}"),
+        }
+    }
+}
+
+pub fn stage(h: &Handle, t: Duration) -> Result<Stage, String> {
+    identify_host(h, t).map(|id| Stage::from_identify(&id))
+}
+
+/// Fail unless the device is in the given stage, as detected beforehand.
+pub fn require_stage(stage: Option<Stage>, want: Stage) -> Result<(), String> {
+    match stage {
+        Some(s) if s == want => Ok(()),
+        None => Err(format!(
+            "Stage unknown, only available in {want} ({})",
+            want.what()
+        )),
+        Some(s) => Err(format!(
+            "Only available in {want} ({}), this is {s} ({})",
+            want.what(),
+            s.what()
+        )),
+    }
+}
+
+pub fn info(h: &Handle, t: Duration) {
+    println!("Read chip information\n");
+    match identify_host(h, t) {
+        Ok(buf) => {
+            let stage = Stage::from_identify(&buf);
             println!("  ROM version:   {}.{}", buf[0], buf[1]);
-            println!("  Stage version: {}.{}", buf[2], buf[3]);
+            println!(
+                "  Stage version: {}.{} ({stage}, {})",
+                buf[2],
+                buf[3],
+                stage.what()
+            );
             println!("  Need password: {}", int_to_bool_str(buf[4]));
             println!("  Password OK:   {}", int_to_bool_str(buf[5]));
             println!();
         }
-        Err(e) => println!("chip_id err: {e:?}"),
+        Err(e) => println!("{e}"),
     }
 }
 
@@ -130,14 +316,21 @@ fn vu32_to_vu8(v: Vec<u32>) -> Vec<u8> {
 
 /// For a start, dump the readable 64k SRAM of an S905D3.
 /// Higher SRAM fails for whatever reason, like many other regions.
-pub fn dump(h: &Handle, t: Duration, addr: u32, size: u32) -> Vec<u8> {
+pub fn dump(h: &Handle, t: Duration, addr: u32, size: u32) -> Result<Vec<u8>, String> {
     println!("Dump memory\n");
+    // The end may be just past the 4G boundary, e.g. for the mask ROM.
+    let end = (addr as u64) + (size as u64);
+    if end > 1 << 32 {
+        return Err(format!(
+            "{size:#x} bytes at {addr:#010x} exceed 32-bit addresses"
+        ));
+    }
     let v: &mut Vec<u32> = &mut Vec::new();
-    for a in (addr..addr + size).step_by(64) {
-        let r = read_block(h, t, a).unwrap();
+    for a in (addr as u64..end).step_by(64) {
+        let r = read_block(h, t, a as u32)?;
         v.extend(r);
     }
-    vu32_to_vu8(v.to_vec())
+    Ok(vu32_to_vu8(v.to_vec()))
 }
 
 fn conv_64u8_as_16u32(buf: &[u8; 64]) -> Vec<u32> {
@@ -162,9 +355,9 @@ fn print_64u8_as_16u32(buf: &[u8; 64]) {
     }
 }
 
-pub fn write(h: &Handle, t: Duration, f: &Vec<u8>, addr: u32) {
-    if f.len() % 64 != 0 {
-        panic!("File size must be multiple of 64 bytes");
+pub fn write(h: &Handle, t: Duration, f: &[u8], addr: u32) -> Result<(), String> {
+    if !f.len().is_multiple_of(64) {
+        return Err("File size must be multiple of 64 bytes".to_string());
     }
     let blocks = f.len() / 64;
     let mut buf = [0u8; 64];
@@ -174,8 +367,9 @@ pub fn write(h: &Handle, t: Duration, f: &Vec<u8>, addr: u32) {
         buf.clone_from_slice(v);
         let c = conv_64u8_as_16u32(&buf);
         let v = vu32_to_vu8(c.to_vec());
-        write_mem(h, t, addr + offs as u32, &v).unwrap();
+        write_mem(h, t, addr + offs as u32, &v)?;
     }
+    Ok(())
 }
 
 // Read and dump chip info at index n.
@@ -212,15 +406,13 @@ pub fn chip_id(h: &Handle, t: Duration) {
     read_mem(h, t, S905X_CHIP_ID_ADDR, 12).unwrap();
 }
 
-pub fn power_states(h: &Handle, t: Duration) {
-    println!("Power states (?):");
-    read_mem(h, t, S905X_CPU_POWER_STATE, 8).unwrap();
-}
-
 pub fn read_reg(h: &Handle, t: Duration, addr: u32) -> Result<u32, String> {
+    crate::journal::check()?;
     let addr_l = addr as u16;
     let addr_h = (addr >> 16) as u16;
-    println!("read memory @{addr_h:04x}{addr_l:04x}");
+    if DEBUG {
+        println!("read memory @{addr_h:04x}{addr_l:04x}");
+    }
     let mut buf = vec![0; 4usize];
     match h.read_control(REQ_TYPE_AMLIN, REQ_READ_MEM, addr_h, addr_l, &mut buf, t) {
         Ok(_) => Ok(u32::from_le_bytes(buf.try_into().unwrap())),
@@ -229,7 +421,7 @@ pub fn read_reg(h: &Handle, t: Duration, addr: u32) -> Result<u32, String> {
 }
 
 /// Read 64 bytes from memory at given address.
-fn read_block(h: &Handle, t: Duration, addr: u32) -> Result<Vec<u32>, String> {
+pub fn read_block(h: &Handle, t: Duration, addr: u32) -> Result<Vec<u32>, String> {
     let addr_l = addr as u16;
     let addr_h = (addr >> 16) as u16;
     let mut buf = [0u8; 64];
@@ -286,6 +478,25 @@ pub fn write_reg(h: &Handle, t: Duration, addr: u32, val: u32) -> Result<(), Str
     write_mem(h, t, addr, &val.to_be_bytes())
 }
 
+/// Read-modify-write a register: first clear, then set, then toggle bits.
+/// Returns the old and the new value.
+pub fn modify_reg(
+    h: &Handle,
+    t: Duration,
+    addr: u32,
+    clear: u32,
+    set: u32,
+    toggle: u32,
+) -> Result<(u32, u32), String> {
+    let old = read_reg(h, t, addr)?;
+    let new = ((old & !clear) | set) ^ toggle;
+    if new != old {
+        crate::journal::note(addr, old);
+        write_reg(h, t, addr, new)?;
+    }
+    Ok((old, new))
+}
+
 pub fn write_mem(h: &Handle, t: Duration, addr: u32, buf: &[u8]) -> Result<(), String> {
     let addr_l = addr as u16;
     let addr_h = (addr >> 16) as u16;
@@ -295,6 +506,10 @@ pub fn write_mem(h: &Handle, t: Duration, addr: u32, buf: &[u8]) -> Result<(), S
     if buf.len() > 64 {
         return Err("Memory write size is 64 max".to_string());
     }
+    crate::journal::check()?;
+    for offs in (0..buf.len() as u32).step_by(4) {
+        crate::journal::record(h, t, addr.wrapping_add(offs))?;
+    }
     let b = vu32_to_vu8(u8_le_slice_to_u32_vec(buf));
     match h.write_control(REQ_TYPE_AMLOUT, REQ_WRITE_MEM, addr_h, addr_l, &b, t) {
         Ok(n) => {
@@ -302,7 +517,7 @@ pub fn write_mem(h: &Handle, t: Duration, addr: u32, buf: &[u8]) -> Result<(), S
                 println!("write_mem success, {n} bytes");
             }
         }
-        Err(e) => println!("write_mem err: {e:?}"),
+        Err(e) => return Err(format!("write_mem err: {e:?}")),
     }
     Ok(())
 }
@@ -314,34 +529,52 @@ pub fn exec(h: &Handle, t: Duration, addr: u32) -> Result<(), String> {
     let b = vec![0; 4usize];
     match h.write_control(REQ_TYPE_AMLOUT, REQ_RUN, addr_h, addr_l, &b, t) {
         Ok(_) => println!("Executed successfully"),
-        Err(e) => println!("Execute error: {e:?}"),
+        Err(e) => return Err(format!("Execute error: {e:?}")),
     }
     Ok(())
 }
 
-pub fn bulk_cmd(h: &Handle, t: Duration, cmd: &str) {
+/// Run a U-Boot command and return the reply from the bulk IN endpoint.
+pub fn bulk_cmd(
+    h: &Handle,
+    t: Duration,
+    stage: Option<Stage>,
+    cmd: &str,
+) -> Result<String, String> {
+    require_stage(stage, Stage::Tpl)?;
     println!("bulk_cmd {cmd}");
     let len = cmd.len();
     if len > 500 {
-        panic!("Command too long.");
+        return Err(format!("Command too long, {len} bytes, 500 max"));
     }
-    let cmd = std::ffi::CString::new(cmd).expect("C sucks");
+    let cmd = std::ffi::CString::new(cmd).map_err(|e| e.to_string())?;
     let mut buf = vec![0; 512usize];
     for (i, &e) in cmd.as_bytes_with_nul().iter().enumerate() {
         buf[i] = e;
     }
-    let res = h.write_control(REQ_TYPE_AMLOUT, REQ_BULK, 0, 2, &buf, t);
-    println!("{res:?}");
+    if let Err(e) = h.write_control(REQ_TYPE_AMLOUT, REQ_BULK, 0, 2, &buf, t) {
+        return Err(format!("bulk_cmd err: {e:?}"));
+    }
+    let mut reply = [0u8; 512];
+    let n = h
+        .read_bulk(&mut reply, t)
+        .map_err(|e| format!("bulk_cmd reply err: {e:?}"))?;
+    let reply = String::from_utf8_lossy(&reply[..n])
+        .trim_end_matches('\0')
+        .to_string();
+    println!("{reply}");
+    Ok(reply)
 }
 
 // The command needs 0-byte termination, hence CString.
-pub fn tpl_cmd(h: &Handle, t: Duration, cmd: &str) {
+pub fn tpl_cmd(h: &Handle, t: Duration, stage: Option<Stage>, cmd: &str) -> Result<(), String> {
+    require_stage(stage, Stage::Tpl)?;
     println!("tpl_cmd {cmd}");
     let len = cmd.len();
     if len > 500 {
-        panic!("Command too long.");
+        return Err(format!("Command too long, {len} bytes, 500 max"));
     }
-    let cmd = std::ffi::CString::new(cmd).expect("C sucks");
+    let cmd = std::ffi::CString::new(cmd).map_err(|e| e.to_string())?;
     let mut buf = vec![0; 512usize];
     for (i, &e) in cmd.as_bytes_with_nul().iter().enumerate() {
         buf[i] = e;
@@ -349,35 +582,35 @@ pub fn tpl_cmd(h: &Handle, t: Duration, cmd: &str) {
     // second part aka sub code - always 1 though?
     let res = h.write_control(REQ_TYPE_AMLOUT, REQ_TPL_CMD, 0, 1, &buf, t);
     println!("{res:?}");
+    Ok(())
 }
 
-// Just for reference; untested as per pyamlboot
 // Password size is 64 bytes
-pub fn password(h: &Handle, t: Duration, buf: &[u8; 64]) {
-    println!("password: {buf:02x?}");
-    let r = h.write_control(REQ_TYPE_AMLOUT, REQ_PASSWORD, 0x0, 0x0, buf, t);
-    println!("{r:?}");
-}
-
-// NOTE: not yet working, just an attempt
-pub fn password_test(h: &Handle, t: Duration) {
-    nop(h, t);
-    let pw = [0xffu8; 64];
-    password(h, t, &pw);
-}
-
-pub fn brute_force_cmds(h: &Handle, t: Duration) {
-    let size = 16;
-    println!("Trying all commands will take about 5 minutes.");
-    for cmd in 0x00..=0xff {
-        println!("Try command {cmd:02x}");
-        let mut buf = vec![0; size as usize];
-        match h.read_control(REQ_TYPE_AMLIN, cmd, 0, 0, &mut buf, t) {
-            Ok(n) => {
-                println!("res ({n}): {buf:02x?}");
-            }
-            Err(e) => println!("err: {e:?}"),
+pub fn password(h: &Handle, t: Duration, buf: &[u8; 64]) -> Result<(), String> {
+    match h.write_control(REQ_TYPE_AMLOUT, REQ_PASSWORD, 0x0, 0x0, buf, t) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("password err: {e:?}")),
+    }
+}
+
+/// Whether the chip needs a password that has not been sent yet
+pub fn is_locked(h: &Handle, t: Duration) -> Result<bool, String> {
+    let id = identify_host(h, t)?;
+    Ok(id[4] == 1 && id[5] != 1)
+}
+
+/// Send the password and check with IdentifyHost that it was accepted.
+pub fn unlock(h: &Handle, t: Duration, pw: &[u8; 64]) -> Result<(), String> {
+    if !is_locked(h, t)? {
+        println!("Chip is not locked");
+        return Ok(());
+    }
+    password(h, t, pw)?;
+    match is_locked(h, t)? {
+        true => Err("Password not accepted, chip is still locked".to_string()),
+        false => {
+            println!("Password accepted");
+            Ok(())
         }
-        std::thread::sleep(std::time::Duration::from_millis(500));
     }
 }
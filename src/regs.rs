@@ -0,0 +1,357 @@
+use crate::protocol::{self, Handle};
+use crate::soc::Soc;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Register database per SoC, see regs/*.toml for the format.
+//
+// The files in regs/ are built in. Files of the same name in the user's
+// config directory (~/.config/aml_boot/regs/) extend them, overriding
+// registers of the same name.
+
+const BUILTIN: [(&str, &str); 6] = [
+    ("gxbb", include_str!("../regs/gxbb.toml")),
+    ("gxl", include_str!("../regs/gxl.toml")),
+    ("gxm", include_str!("../regs/gxm.toml")),
+    ("g12a", include_str!("../regs/g12a.toml")),
+    ("g12b", include_str!("../regs/g12b.toml")),
+    ("sm1", include_str!("../regs/sm1.toml")),
+];
+
+/// Directory for user data files, e.g. ~/.config/aml_boot
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(d) => PathBuf::from(d),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("aml_boot"))
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    #[default]
+    Rw,
+    Ro,
+    Wo,
+}
+
+/// Bit range of a field, inclusive
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct Bits {
+    pub hi: u8,
+    pub lo: u8,
+}
+
+impl TryFrom<String> for Bits {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let parse = |v: &str| match v.trim().parse::<u8>() {
+            Ok(b) if b < 32 => Ok(b),
+            _ => Err(format!("bad bit number '{v}'")),
+        };
+        let (hi, lo) = match s.split_once(':') {
+            Some((hi, lo)) => (parse(hi)?, parse(lo)?),
+            None => (parse(&s)?, parse(&s)?),
+        };
+        if hi < lo {
+            return Err(format!("bad bit range '{s}'"));
+        }
+        Ok(Bits { hi, lo })
+    }
+}
+
+impl std::fmt::Display for Bits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.hi == self.lo {
+            write!(f, "{}", self.lo)
+        } else {
+            write!(f, "{}:{}", self.hi, self.lo)
+        }
+    }
+}
+
+impl Bits {
+    pub fn mask(&self) -> u32 {
+        (u32::MAX >> (31 - self.hi)) & (u32::MAX << self.lo)
+    }
+
+    pub fn width(&self) -> u32 {
+        (self.hi - self.lo + 1) as u32
+    }
+
+    pub fn get(&self, reg: u32) -> u32 {
+        (reg & self.mask()) >> self.lo
+    }
+
+    pub fn set(&self, reg: u32, val: u32) -> Result<u32, String> {
+        let max = self.mask() >> self.lo;
+        if val > max {
+            return Err(format!("value {val:#x} does not fit in bits {self}"));
+        }
+        Ok((reg & !self.mask()) | (val << self.lo))
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Field {
+    pub name: String,
+    pub bits: Bits,
+    #[serde(default)]
+    pub desc: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Register {
+    pub name: String,
+    pub addr: u32,
+    pub reset: Option<u32>,
+    #[serde(default)]
+    pub access: Access,
+    #[serde(default)]
+    pub desc: String,
+    #[serde(default)]
+    pub fields: Vec<Field>,
+}
+
+impl Register {
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Apply `FIELD=value` assignments to a register value.
+    pub fn assign(&self, mut v: u32, assignments: &[String]) -> Result<u32, String> {
+        for a in assignments {
+            let (name, val) = a
+                .split_once('=')
+                .ok_or(format!("expected FIELD=value, got '{a}'"))?;
+            let f = self
+                .field(name)
+                .ok_or(format!("{} has no field {name}", self.name))?;
+            let val = crate::script::parse_u32(val)?;
+            v = f.bits.set(v, val)?;
+        }
+        Ok(v)
+    }
+
+    /// Print a value along with its fields.
+    pub fn print(&self, v: u32) {
+        println!("{} @{:08x} = {v:08x}", self.name, self.addr);
+        if !self.desc.is_empty() {
+            println!("  {}", self.desc);
+        }
+        if let Some(r) = self.reset {
+            if r != v {
+                println!("  (reset value {r:08x})");
+            }
+        }
+        for f in &self.fields {
+            let bits = format!("[{}]", f.bits);
+            let fv = f.bits.get(v);
+            println!("  {:<16} {bits:<8} = {fv:#x}  {}", f.name, f.desc);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RegFile {
+    inherit: Option<String>,
+    #[serde(default, rename = "reg")]
+    regs: Vec<Register>,
+}
+
+pub struct RegDb {
+    pub soc: Soc,
+    pub regs: Vec<Register>,
+}
+
+fn parse_file(name: &str, src: &str) -> Result<RegFile, String> {
+    toml::from_str(src).map_err(|e| format!("{name}: {e}"))
+}
+
+// Later definitions of a register replace earlier ones.
+fn merge(regs: &mut Vec<Register>, new: Vec<Register>) {
+    for r in new {
+        match regs.iter_mut().find(|o| o.name == r.name) {
+            Some(o) => *o = r,
+            None => regs.push(r),
+        }
+    }
+}
+
+fn load_builtin(name: &str, regs: &mut Vec<Register>) -> Result<(), String> {
+    let (_, src) = BUILTIN
+        .iter()
+        .find(|(n, _)| *n == name)
+        .ok_or(format!("No register database for {name}"))?;
+    let f = parse_file(name, src)?;
+    if let Some(base) = &f.inherit {
+        load_builtin(base, regs)?;
+    }
+    merge(regs, f.regs);
+    Ok(())
+}
+
+impl RegDb {
+    pub fn load(soc: Soc) -> Result<RegDb, String> {
+        let name = soc.to_string();
+        let mut regs = Vec::new();
+        load_builtin(&name, &mut regs)?;
+        if let Some(dir) = config_dir() {
+            let p = dir.join("regs").join(format!("{name}.toml"));
+            if let Ok(src) = std::fs::read_to_string(&p) {
+                let f = parse_file(&p.to_string_lossy(), &src)?;
+                merge(&mut regs, f.regs);
+            }
+        }
+        Ok(RegDb { soc, regs })
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Register> {
+        self.regs.iter().find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Register, String> {
+        self.find(name)
+            .ok_or(format!("Unknown register {name} for {}", self.soc))
+    }
+}
+
+/// Resolve a register name or a number to an address.
+pub fn resolve(db: &RegDb, s: &str) -> Result<u32, String> {
+    match crate::script::parse_u32(s) {
+        Ok(a) => Ok(a),
+        Err(_) => Ok(db.get(s)?.addr),
+    }
+}
+
+pub fn list(db: &RegDb, filter: &str) {
+    let filter = filter.to_ascii_uppercase();
+    for r in db.regs.iter().filter(|r| r.name.contains(&filter)) {
+        println!("  {:08x} {:<24} {}", r.addr, r.name, r.desc);
+    }
+}
+
+pub fn get(h: &Handle, t: Duration, db: &RegDb, name: &str) -> Result<(), String> {
+    let r = db.get(name)?;
+    if r.access == Access::Wo {
+        return Err(format!("{} is write-only", r.name));
+    }
+    let v = protocol::read_reg(h, t, r.addr)?;
+    r.print(v);
+    Ok(())
+}
+
+/// Write a register, either a plain value or `FIELD=value` assignments as a
+/// read-modify-write.
+pub fn set(h: &Handle, t: Duration, db: &RegDb, name: &str, args: &[String]) -> Result<(), String> {
+    let r = db.get(name)?;
+    if r.access == Access::Ro {
+        return Err(format!("{} is read-only", r.name));
+    }
+    let v = match args {
+        [v] if !v.contains('=') => crate::script::parse_u32(v)?,
+        _ => {
+            let old = protocol::read_reg(h, t, r.addr)?;
+            r.assign(old, args)?
+        }
+    };
+    protocol::write_reg(h, t, r.addr, v)?;
+    if r.access == Access::Rw {
+        let v = protocol::read_reg(h, t, r.addr)?;
+        r.print(v);
+    }
+    Ok(())
+}
+
+fn bin(v: u32) -> String {
+    let s = format!("{v:032b}");
+    let nibbles: Vec<&str> = (0..32).step_by(4).map(|i| &s[i..i + 4]).collect();
+    nibbles.join("_")
+}
+
+/// Print old and new value in binary, marking the bits that changed.
+pub fn print_change(old: u32, new: u32) {
+    println!("  before: {old:08x}  {}", bin(old));
+    println!("  after:  {new:08x}  {}", bin(new));
+    let marks: String = bin(old ^ new)
+        .chars()
+        .map(|c| if c == '1' { '^' } else { ' ' })
+        .collect();
+    if old == new {
+        println!("  (unchanged)");
+    } else {
+        println!("{:20}{marks}", "");
+    }
+}
+
+/// Parse `hi:lo=value` into the bits to clear and to set.
+pub fn parse_field_value(s: &str) -> Result<(u32, u32), String> {
+    let (bits, val) = s
+        .split_once('=')
+        .ok_or(format!("expected hi:lo=value, got '{s}'"))?;
+    let bits = Bits::try_from(bits.to_string())?;
+    let val = crate::script::parse_u32(val)?;
+    Ok((bits.mask(), bits.set(0, val)?))
+}
+
+/// Read-modify-write with set/clear/toggle masks and bit fields.
+pub fn modify(
+    h: &Handle,
+    t: Duration,
+    addr: u32,
+    set: u32,
+    clear: u32,
+    toggle: u32,
+    fields: &[String],
+) -> Result<(), String> {
+    let (mut clear, mut set) = (clear, set);
+    for f in fields {
+        let (c, s) = parse_field_value(f)?;
+        clear |= c;
+        set = (set & !c) | s;
+    }
+    let (old, new) = protocol::modify_reg(h, t, addr, clear, set, toggle)?;
+    println!("{addr:08x}:");
+    print_change(old, new);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(s: &str) -> Bits {
+        Bits::try_from(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(bits("7").to_string(), "7");
+        assert_eq!(bits("31:16").to_string(), "31:16");
+        for s in ["32", "3:4", "x", "1:"] {
+            assert!(Bits::try_from(s.to_string()).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn get() {
+        assert_eq!(bits("31:0").get(0xdead_beef), 0xdead_beef);
+        assert_eq!(bits("15:8").get(0xdead_beef), 0xbe);
+        assert_eq!(bits("31").get(0x8000_0000), 1);
+        assert_eq!(bits("0").get(0xffff_fffe), 0);
+    }
+
+    #[test]
+    fn set() {
+        assert_eq!(bits("15:8").set(0xdead_beef, 0x12), Ok(0xdead_12ef));
+        assert_eq!(bits("31:0").set(0, 0xffff_ffff), Ok(0xffff_ffff));
+        assert_eq!(bits("0").set(0xffff_ffff, 0), Ok(0xffff_fffe));
+        assert!(bits("3:0").set(0, 0x10).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::caps;
+use crate::protocol::{self, Handle, Stage, REQ_CHIPINFO};
+use crate::script::{self, parse_u32};
+use rustyline::error::ReadlineError;
+use std::io::Write;
+use std::time::Duration;
+
+// Interactive session on one open handle.
+// Besides the commands below, all script operations are available.
+
+const HISTORY_FILE: &str = ".aml_boot_history";
+
+const HELP: &str = "\
+  peek     <addr> [<count>]       read up to 64 bytes
+  poke     <addr> <value>         write a 32-bit value
+  dump     <addr> <size> <file>   dump memory to file (multiple of 64 bytes)
+  load     <file> <addr>          write file to memory
+  exec     <addr>                 execute code at address
+  chipinfo [<page>]               read chip info, all pages by default
+  bulkcmd  <command...>           send a bulk command (U-Boot)
+  info                            read ROM/stage version, update prompt
+  help                            show this help
+  quit                            leave
+
+  Script operations (read, write, sleep, poll, expect) work as well.";
+
+fn history_path() -> Option<std::path::PathBuf> {
+    std::env::var_os("HOME").map(|h| std::path::Path::new(&h).join(HISTORY_FILE))
+}
+
+fn prompt(h: &Handle, t: Duration, mode: &str) -> String {
+    match protocol::identify_host(h, t) {
+        Ok(b) => format!(
+            "{mode} rom {}.{} {}> ",
+            b[0],
+            b[1],
+            Stage::from_identify(&b)
+        ),
+        Err(_) => format!("{mode} ?> "),
+    }
+}
+
+fn eval(h: &Handle, t: Duration, line: &str) -> Result<(), String> {
+    let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let args: Vec<&str> = rest.split_whitespace().collect();
+    let arg = |i: usize| -> Result<u32, String> {
+        let a = args
+            .get(i)
+            .ok_or(format!("{cmd}: missing argument {}", i + 1))?;
+        parse_u32(a)
+    };
+    match cmd {
+        "peek" => {
+            let count = if args.len() > 1 { arg(1)? } else { 4 };
+            let count = u8::try_from(count).map_err(|e| e.to_string())?;
+            protocol::read_mem(h, t, arg(0)?, count)?;
+        }
+        "poke" => {
+            protocol::write_reg(h, t, arg(0)?, arg(1)?)?;
+        }
+        "dump" => {
+            let file_name = args.get(2).ok_or("dump: missing file name")?;
+            let size = arg(1)?;
+            if !size.is_multiple_of(64) {
+                return Err("dump: size must be multiple of 64 bytes".to_string());
+            }
+            let res = protocol::dump(h, t, arg(0)?, size)?;
+            let mut file = std::fs::File::create(file_name).map_err(|e| e.to_string())?;
+            file.write_all(&res).map_err(|e| e.to_string())?;
+            println!("{} bytes written to {file_name}", res.len());
+        }
+        "chipinfo" => {
+            caps::get_or_unknown(h, t, false).require(REQ_CHIPINFO)?;
+            match args.first() {
+                Some(_) => protocol::chip_info_n(h, t, arg(0)? as u16),
+                None => protocol::chip_info(h, t),
+            }
+        }
+        "info" => protocol::info(h, t),
+        "help" => println!("{HELP}"),
+        _ => {
+            if let Some(step) = script::parse_line(line)? {
+                // The stage changes within a session, e.g. after `run`.
+                let stage = protocol::stage(h, t).ok();
+                script::run_step(h, t, stage, &step)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn run(h: &Handle, t: Duration, mode: &str) -> Result<(), String> {
+    let mut rl = rustyline::DefaultEditor::new().map_err(|e| e.to_string())?;
+    let history = history_path();
+    if let Some(p) = &history {
+        // There is no history on first use.
+        let _ = rl.load_history(p);
+    }
+    println!("Type 'help' for commands.");
+    let mut p = prompt(h, t, mode);
+    loop {
+        match rl.readline(&p) {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let _ = rl.add_history_entry(line);
+                if matches!(line, "quit" | "exit") {
+                    break;
+                }
+                if let Err(e) = eval(h, t, line) {
+                    println!("Error: {e}");
+                }
+                // Things like `exec` or `bulkcmd` may change the stage.
+                p = prompt(h, t, mode);
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    if let Some(p) = &history {
+        rl.save_history(p).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
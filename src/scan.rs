@@ -0,0 +1,293 @@
+use crate::protocol::{self, Handle, REQ_CHIP_GEN, REQ_TYPE_AMLIN, REQ_TYPE_AMLOUT};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+// Request scanner: tries control requests and classifies what the ROM does
+// with them. Results go to a JSON file after every request, so a scan that
+// lost the device (some requests reset it) resumes where it stopped, and two
+// scans, e.g. of different ROM revisions, can be diffed.
+
+#[derive(
+    ValueEnum, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Dir {
+    In,
+    Out,
+}
+
+/// An inclusive range, given as `lo-hi` or a single number.
+#[derive(Copy, Clone, Debug)]
+pub struct Range {
+    pub lo: u16,
+    pub hi: u16,
+}
+
+pub fn parse_range(s: &str) -> Result<Range, String> {
+    let num = |s: &str| clap_num::maybe_hex::<u16>(s.trim());
+    let (lo, hi) = match s.split_once('-') {
+        Some((lo, hi)) => (num(lo)?, num(hi)?),
+        None => (num(s)?, num(s)?),
+    };
+    match lo <= hi {
+        true => Ok(Range { lo, hi }),
+        false => Err(format!("Empty range {s}")),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "class", content = "data", rename_all = "lowercase")]
+pub enum Class {
+    /// The request was stalled, i.e. it is not implemented.
+    Stall,
+    Timeout,
+    /// IN data equal to the `REQ_CHIP_GEN` answer, the ROM's fallback.
+    Default,
+    /// IN data that differs from the fallback, as hex
+    Data(String),
+    /// OUT request accepted, with the number of bytes sent
+    Accepted(usize),
+    /// The device disconnected, so the request is not tried again on resume.
+    Gone,
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    pub dir: Dir,
+    pub req: u8,
+    pub value: u16,
+    pub index: u16,
+    pub len: u16,
+    pub result: Class,
+}
+
+type Key = (Dir, u8, u16, u16, u16);
+
+impl Entry {
+    fn key(&self) -> Key {
+        (self.dir, self.req, self.value, self.index, self.len)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Scan {
+    pub product: Option<String>,
+    pub rom: String,
+    pub stage: String,
+    pub results: Vec<Entry>,
+}
+
+pub struct Params {
+    pub dirs: Vec<Dir>,
+    pub reqs: Range,
+    pub values: Range,
+    pub indices: Range,
+    pub len: u16,
+    pub delay: Duration,
+    /// Also send OUT requests the tool knows, e.g. `REQ_RUN`
+    pub known: bool,
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn load(file_name: &str) -> Result<Scan, String> {
+    let s = std::fs::read_to_string(file_name).map_err(|e| format!("{file_name}: {e}"))?;
+    serde_json::from_str(&s).map_err(|e| format!("{file_name}: {e}"))
+}
+
+fn save(file_name: &str, scan: &Scan) -> Result<(), String> {
+    let j = serde_json::to_string_pretty(scan).map_err(|e| e.to_string())?;
+    std::fs::write(file_name, j).map_err(|e| format!("{file_name}: {e}"))
+}
+
+fn describe(e: &Entry) -> String {
+    let name = protocol::req_name(e.req).unwrap_or("");
+    let dir = match e.dir {
+        Dir::In => "IN ",
+        Dir::Out => "OUT",
+    };
+    format!(
+        "{dir} {:02x} {name:13} value {:04x} index {:04x} len {:3}",
+        e.req, e.value, e.index, e.len
+    )
+}
+
+fn show(c: &Class) -> String {
+    match c {
+        Class::Stall => "stall".to_string(),
+        Class::Timeout => "timeout".to_string(),
+        Class::Default => "default".to_string(),
+        Class::Data(d) => format!("data {d}"),
+        Class::Accepted(n) => format!("accepted {n}"),
+        Class::Gone => "device gone".to_string(),
+        Class::Error(e) => format!("error {e}"),
+    }
+}
+
+fn classify(r: rusb::Result<Class>) -> Class {
+    match r {
+        Ok(c) => c,
+        Err(rusb::Error::Pipe) => Class::Stall,
+        Err(rusb::Error::Timeout) => Class::Timeout,
+        Err(rusb::Error::NoDevice) => Class::Gone,
+        Err(e) => Class::Error(format!("{e:?}")),
+    }
+}
+
+/// Scan the requests in `p`, continuing a previous scan in `file_name`.
+pub fn run(
+    h: &Handle,
+    t: Duration,
+    p: &Params,
+    product: Option<String>,
+    file_name: &str,
+) -> Result<(), String> {
+    if p.reqs.hi > 0xff {
+        return Err(format!("Requests are 8 bits, got {:x}", p.reqs.hi));
+    }
+    let id = protocol::identify_host(h, t)?;
+    let rom = format!("{}.{}", id[0], id[1]);
+    let stage = format!("{}.{}", id[2], id[3]);
+
+    let mut scan = match std::path::Path::new(file_name).exists() {
+        true => {
+            let s = load(file_name)?;
+            if s.rom != rom || s.stage != stage {
+                return Err(format!(
+                    "{file_name} is a scan of ROM {} stage {}, this is ROM {rom} stage {stage}",
+                    s.rom, s.stage
+                ));
+            }
+            println!("Resuming {file_name}, {} results so far", s.results.len());
+            s
+        }
+        false => Scan {
+            product,
+            rom,
+            stage,
+            results: Vec::new(),
+        },
+    };
+    let done: HashSet<Key> = scan.results.iter().map(Entry::key).collect();
+
+    let mut todo = Vec::new();
+    for &dir in &p.dirs {
+        for req in p.reqs.lo..=p.reqs.hi {
+            let req = req as u8;
+            if dir == Dir::Out && !p.known && protocol::req_name(req).is_some() {
+                continue;
+            }
+            for value in p.values.lo..=p.values.hi {
+                for index in p.indices.lo..=p.indices.hi {
+                    let key = (dir, req, value, index, p.len);
+                    if !done.contains(&key) {
+                        todo.push(key);
+                    }
+                }
+            }
+        }
+    }
+    let secs = todo.len() as u64 * p.delay.as_millis() as u64 / 1000;
+    println!("{} requests to try, about {secs} s", todo.len());
+
+    // What the ROM answers to requests it does not know
+    let mut fallback = vec![0; p.len as usize];
+    let fallback = match h.read_control(REQ_TYPE_AMLIN, REQ_CHIP_GEN, 0, 0, &mut fallback, t) {
+        Ok(n) => Some(fallback[..n].to_vec()),
+        Err(_) => None,
+    };
+
+    for (dir, req, value, index, len) in todo {
+        let mut buf = vec![0; len as usize];
+        let r = match dir {
+            Dir::In => h
+                .read_control(REQ_TYPE_AMLIN, req, value, index, &mut buf, t)
+                .map(|n| match &fallback {
+                    Some(f) if req != REQ_CHIP_GEN && f[..] == buf[..n] => Class::Default,
+                    _ => Class::Data(hex(&buf[..n])),
+                }),
+            Dir::Out => h
+                .write_control(REQ_TYPE_AMLOUT, req, value, index, &buf, t)
+                .map(Class::Accepted),
+        };
+        let e = Entry {
+            dir,
+            req,
+            value,
+            index,
+            len,
+            result: classify(r),
+        };
+        println!("{}  {}", describe(&e), show(&e.result));
+        let gone = e.result == Class::Gone;
+        scan.results.push(e);
+        save(file_name, &scan)?;
+        if gone {
+            return Err(format!(
+                "Device gone at request {req:02x}, saved {file_name}; reconnect and run again to resume"
+            ));
+        }
+        std::thread::sleep(p.delay);
+    }
+    Ok(())
+}
+
+/// Compare two scans, e.g. of different ROM revisions.
+pub fn diff(a: &str, b: &str) -> Result<(), String> {
+    let sa = load(a)?;
+    let sb = load(b)?;
+    println!("a: {a}: ROM {} stage {}", sa.rom, sa.stage);
+    println!("b: {b}: ROM {} stage {}", sb.rom, sb.stage);
+
+    let ma: BTreeMap<_, _> = sa.results.iter().map(|e| (e.key(), e)).collect();
+    let mb: BTreeMap<_, _> = sb.results.iter().map(|e| (e.key(), e)).collect();
+    let mut same = 0;
+    for (k, ea) in &ma {
+        match mb.get(k) {
+            Some(eb) if ea.result == eb.result => same += 1,
+            Some(eb) => {
+                println!("{}", describe(ea));
+                println!("  a: {}", show(&ea.result));
+                println!("  b: {}", show(&eb.result));
+            }
+            None => println!("{}  only in a: {}", describe(ea), show(&ea.result)),
+        }
+    }
+    for (k, eb) in &mb {
+        if !ma.contains_key(k) {
+            println!("{}  only in b: {}", describe(eb), show(&eb.result));
+        }
+    }
+    println!("{same} requests behave the same");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> (u16, u16) {
+        let r = parse_range(s).unwrap();
+        (r.lo, r.hi)
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(range("5"), (5, 5));
+        assert_eq!(range("0-3"), (0, 3));
+        assert_eq!(range("0x10 - 0x1f"), (0x10, 0x1f));
+        assert_eq!(range("0-0xffff"), (0, 0xffff));
+    }
+
+    #[test]
+    fn bad_ranges() {
+        for s in ["", "3-1", "0-0x10000", "a-b", "1-"] {
+            assert!(parse_range(s).is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::protocol::{self, Handle, Stage};
+use std::time::{Duration, Instant};
+
+// Run a sequence of loader operations in one USB session.
+//
+// One operation per line, `#` starts a comment. Numbers may be hex (0x...).
+//
+//   read    <addr>                        print a 32-bit register
+//   write   <addr> <value>                write a 32-bit register
+//   load    <file> <addr>                 write a file to memory
+//   exec    <addr>                        execute code at address
+//   sleep   <ms>                          wait
+//   poll    <addr> <mask> <value> [<ms>]  wait until (reg & mask) == value
+//   expect  <addr> <mask> <value>         fail unless (reg & mask) == value
+//   bulkcmd <command...>                  send a bulk command (U-Boot)
+//
+// The whole script is parsed before anything is sent to the device.
+
+const DEFAULT_POLL_TIMEOUT: u64 = 1000;
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    Read(u32),
+    Write(u32, u32),
+    Load(String, u32),
+    Exec(u32),
+    Sleep(u64),
+    Poll(u32, u32, u32, u64),
+    Expect(u32, u32, u32),
+    BulkCmd(String),
+}
+
+/// Parse a number, hex with 0x prefix, allowing `_` separators.
+pub fn parse_u32(s: &str) -> Result<u32, String> {
+    clap_num::maybe_hex(&s.replace('_', ""))
+}
+
+pub fn parse_line(line: &str) -> Result<Option<Step>, String> {
+    let line = match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let line = line.trim();
+    let (op, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if op.is_empty() {
+        return Ok(None);
+    }
+    if op == "bulkcmd" {
+        let cmd = rest.trim();
+        if cmd.is_empty() {
+            return Err("bulkcmd needs a command".to_string());
+        }
+        return Ok(Some(Step::BulkCmd(cmd.to_string())));
+    }
+    let args: Vec<&str> = rest.split_whitespace().collect();
+    let num = |i: usize| -> Result<u32, String> {
+        let a = args
+            .get(i)
+            .ok_or(format!("{op}: missing argument {}", i + 1))?;
+        parse_u32(a).map_err(|e| format!("{op}: {a}: {e}"))
+    };
+    let argc = |min: usize, max: usize| -> Result<(), String> {
+        if args.len() < min || args.len() > max {
+            return Err(format!("{op}: expected {min} to {max} arguments"));
+        }
+        Ok(())
+    };
+    let step = match op {
+        "read" => {
+            argc(1, 1)?;
+            Step::Read(num(0)?)
+        }
+        "write" => {
+            argc(2, 2)?;
+            Step::Write(num(0)?, num(1)?)
+        }
+        "load" => {
+            argc(2, 2)?;
+            Step::Load(args[0].to_string(), num(1)?)
+        }
+        "exec" => {
+            argc(1, 1)?;
+            Step::Exec(num(0)?)
+        }
+        "sleep" => {
+            argc(1, 1)?;
+            Step::Sleep(num(0)? as u64)
+        }
+        "poll" => {
+            argc(3, 4)?;
+            let ms = if args.len() == 4 {
+                num(3)? as u64
+            } else {
+                DEFAULT_POLL_TIMEOUT
+            };
+            Step::Poll(num(0)?, num(1)?, num(2)?, ms)
+        }
+        "expect" => {
+            argc(3, 3)?;
+            Step::Expect(num(0)?, num(1)?, num(2)?)
+        }
+        _ => return Err(format!("unknown operation '{op}'")),
+    };
+    Ok(Some(step))
+}
+
+/// Parse a script into steps, along with their line numbers.
+pub fn parse(src: &str) -> Result<Vec<(usize, Step)>, String> {
+    let mut steps = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let n = i + 1;
+        match parse_line(line) {
+            Ok(Some(s)) => steps.push((n, s)),
+            Ok(None) => {}
+            Err(e) => return Err(format!("line {n}: {e}")),
+        }
+    }
+    Ok(steps)
+}
+
+pub fn run_step(h: &Handle, t: Duration, stage: Option<Stage>, step: &Step) -> Result<(), String> {
+    match step {
+        Step::Read(addr) => {
+            let v = protocol::read_reg(h, t, *addr)?;
+            println!("  {addr:08x}: {v:08x}");
+        }
+        Step::Write(addr, val) => {
+            protocol::write_reg(h, t, *addr, *val)?;
+        }
+        Step::Load(file_name, addr) => {
+            let mut file = std::fs::read(file_name).map_err(|e| format!("{file_name}: {e}"))?;
+            // The loader takes 64 byte blocks only.
+            let pad = (64 - file.len() % 64) % 64;
+            file.resize(file.len() + pad, 0);
+            protocol::write(h, t, &file, *addr)?;
+        }
+        Step::Exec(addr) => {
+            protocol::exec(h, t, *addr)?;
+        }
+        Step::Sleep(ms) => {
+            std::thread::sleep(Duration::from_millis(*ms));
+        }
+        Step::Poll(addr, mask, val, ms) => {
+            let start = Instant::now();
+            let timeout = Duration::from_millis(*ms);
+            loop {
+                let v = protocol::read_reg(h, t, *addr)?;
+                if v & mask == *val {
+                    break;
+                }
+                if start.elapsed() > timeout {
+                    return Err(format!(
+                        "timeout polling {addr:08x}: {v:08x} & {mask:08x} != {val:08x}"
+                    ));
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+        Step::Expect(addr, mask, val) => {
+            let v = protocol::read_reg(h, t, *addr)?;
+            if v & mask != *val {
+                return Err(format!(
+                    "expected {addr:08x} & {mask:08x} == {val:08x}, got {v:08x}"
+                ));
+            }
+        }
+        Step::BulkCmd(cmd) => {
+            protocol::bulk_cmd(h, t, stage, cmd)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run all steps, stopping at the first failure.
+pub fn run(
+    h: &Handle,
+    t: Duration,
+    stage: Option<Stage>,
+    steps: &[(usize, Step)],
+) -> Result<(), String> {
+    for (n, step) in steps {
+        println!("{n:>4}: {step:x?}");
+        run_step(h, t, stage, step).map_err(|e| format!("line {n}: {e}"))?;
+    }
+    println!("Script done, {} steps", steps.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(line: &str) -> Step {
+        parse_line(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn operations() {
+        assert_eq!(step("read 0xc8100024"), Step::Read(0xc810_0024));
+        assert_eq!(step("write 0xc810_0024 16"), Step::Write(0xc810_0024, 16));
+        assert_eq!(
+            step("load bl2.bin 0xfffa0000"),
+            Step::Load("bl2.bin".to_string(), 0xfffa_0000)
+        );
+        assert_eq!(step("exec 0xfffa0000"), Step::Exec(0xfffa_0000));
+        assert_eq!(step("sleep 100"), Step::Sleep(100));
+        assert_eq!(
+            step("poll 0x10 0x1 0x1"),
+            Step::Poll(0x10, 1, 1, DEFAULT_POLL_TIMEOUT)
+        );
+        assert_eq!(step("poll 0x10 0x1 0 50"), Step::Poll(0x10, 1, 0, 50));
+        assert_eq!(step("expect 0x10 0xff 0x2"), Step::Expect(0x10, 0xff, 2));
+        assert_eq!(
+            step("bulkcmd   setenv bootcmd run x  "),
+            Step::BulkCmd("setenv bootcmd run x".to_string())
+        );
+    }
+
+    #[test]
+    fn comments_and_blanks() {
+        assert_eq!(parse_line("").unwrap(), None);
+        assert_eq!(parse_line("   # only a comment").unwrap(), None);
+        assert_eq!(step("  read 0x10  # status"), Step::Read(0x10));
+    }
+
+    #[test]
+    fn bad_lines() {
+        for line in [
+            "read",
+            "read 0x10 0x20",
+            "write 0x10",
+            "poll 0x10 1 1 1 1",
+            "sleep soon",
+            "bulkcmd",
+            "jump 0x10",
+        ] {
+            assert!(parse_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn parse_whole_script_first() {
+        let src = "write 0x10 1\n\n# wait\nsleep 10\n";
+        let steps = parse(src).unwrap();
+        assert_eq!(steps, [(1, Step::Write(0x10, 1)), (4, Step::Sleep(10))]);
+        // an error on a later line fails the whole script, with its number
+        let src = "write 0x10 1\nexec 0x20\nwrite 0x10\n";
+        assert_eq!(
+            parse(src).unwrap_err(),
+            "line 3: write: expected 2 to 2 arguments"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use crate::board::{Board, Io};
+use crate::gpio::{self, Mode};
+use crate::journal;
+use crate::protocol::Handle;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+// Factory smoke test from the mask ROM loader: needs an operator to look at
+// the LEDs and press the buttons, but no OS on the board.
+
+const BUTTON_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// The answer is read in a thread, so that Ctrl-C is noticed while waiting.
+fn confirm(question: &str) -> Result<bool, String> {
+    print!("{question} [y/n] ");
+    std::io::stdout().flush().map_err(|e| e.to_string())?;
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let r = std::io::stdin().lock().read_line(&mut line).map(|_| line);
+        let _ = tx.send(r);
+    });
+    loop {
+        journal::check()?;
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(r) => {
+                let line = r.map_err(|e| e.to_string())?;
+                return Ok(line.trim().eq_ignore_ascii_case("y"));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err("No answer".to_string()),
+        }
+    }
+}
+
+fn test_led(h: &Handle, t: Duration, b: &Board, l: &Io) -> Result<bool, String> {
+    let p = gpio::pin(b.soc, &l.pin)?;
+    gpio::set(h, t, &p, l.level(true))?;
+    let on = confirm(&format!("LED {} ({}) on?", l.name, l.pin))?;
+    gpio::set(h, t, &p, l.level(false))?;
+    let off = confirm(&format!("LED {} ({}) off?", l.name, l.pin))?;
+    Ok(on && off)
+}
+
+fn test_button(h: &Handle, t: Duration, b: &Board, k: &Io) -> Result<bool, String> {
+    let p = gpio::pin(b.soc, &k.pin)?;
+    gpio::set_mode(h, t, &p, Mode::In)?;
+    if gpio::get_level(h, t, &p)? == k.level(true) {
+        println!(
+            "Button {} ({}) is pressed already, release it",
+            k.name, k.pin
+        );
+        return Ok(false);
+    }
+    println!("Press button {} ({})...", k.name, k.pin);
+    let start = Instant::now();
+    while start.elapsed() < BUTTON_TIMEOUT {
+        if gpio::get_level(h, t, &p)? == k.level(true) {
+            return Ok(true);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    println!("No press within {}s", BUTTON_TIMEOUT.as_secs());
+    Ok(false)
+}
+
+/// Cycle the LEDs, wait for the buttons and report the straps of a board.
+pub fn run(h: &Handle, t: Duration, b: &Board) -> Result<(), String> {
+    println!("Self-test for {}", b.name);
+    let mut results = Vec::new();
+    for l in &b.leds {
+        results.push((format!("LED {}", l.name), test_led(h, t, b, l)?));
+    }
+    for k in &b.buttons {
+        results.push((format!("button {}", k.name), test_button(h, t, b, k)?));
+    }
+
+    println!();
+    for s in &b.straps {
+        let p = gpio::pin(b.soc, &s.pin)?;
+        let level = gpio::get_level(h, t, &p)?;
+        println!("  strap  {:<16} {:<10} {level:?}", s.name, s.pin);
+    }
+    for (name, ok) in &results {
+        println!("  {:<23} {}", name, if *ok { "PASS" } else { "FAIL" });
+    }
+
+    let failed = results.iter().filter(|(_, ok)| !ok).count();
+    match failed {
+        0 => Ok(()),
+        n => Err(format!("{n} of {} tests failed", results.len())),
+    }
+}
@@ -0,0 +1,73 @@
+use crate::protocol::{self, Handle};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::time::Duration;
+
+// SoC identification, following Linux drivers/soc/amlogic/meson-gx-socinfo.c
+//
+// The mask ROM version tells the generation apart, which we need to know
+// first: the SoC info register sits on a different bus on G12 and later, and
+// reading unmapped addresses may crash the ROM.
+
+// AO_SEC_SD_CFG8
+const GX_SOCINFO: u32 = 0xc810_0220;
+const G12_SOCINFO: u32 = 0xff80_0220;
+
+#[derive(ValueEnum, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Soc {
+    /// S905
+    Gxbb,
+    /// S905X, S905D, S805X
+    Gxl,
+    /// S912
+    Gxm,
+    /// S905X2, S905D2, S905Y2
+    G12a,
+    /// A311D, S922X
+    G12b,
+    /// S905X3, S905D3, S905Y3
+    Sm1,
+}
+
+impl std::fmt::Display for Soc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl Soc {
+    /// G12A, G12B and SM1 share most of their register layout.
+    pub fn is_g12(self) -> bool {
+        matches!(self, Soc::G12a | Soc::G12b | Soc::Sm1)
+    }
+
+    fn from_major_id(id: u8) -> Option<Soc> {
+        match id {
+            0x1f => Some(Soc::Gxbb),
+            0x21 => Some(Soc::Gxl),
+            0x22 => Some(Soc::Gxm),
+            0x28 => Some(Soc::G12a),
+            0x29 => Some(Soc::G12b),
+            0x2b => Some(Soc::Sm1),
+            _ => None,
+        }
+    }
+}
+
+/// Read the SoC info register and map its major ID to a SoC.
+pub fn detect(h: &Handle, t: Duration) -> Result<Soc, String> {
+    let id = protocol::identify_host(h, t)?;
+    // ROM 2.x on GX, 3.x on G12A and later
+    let addr = match id[0] {
+        2 => GX_SOCINFO,
+        3 => G12_SOCINFO,
+        v => return Err(format!("Unknown ROM version {v}.{}", id[1])),
+    };
+    let v = protocol::read_reg(h, t, addr)?;
+    let major = (v >> 24) as u8;
+    Soc::from_major_id(major).ok_or(format!("Unknown SoC, info {v:08x}"))
+}
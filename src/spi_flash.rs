@@ -0,0 +1,375 @@
+use crate::clocks;
+use crate::pinmux;
+use crate::protocol::{self, Handle};
+use crate::soc::Soc;
+use std::time::{Duration, Instant};
+
+// SPI NOR flash through the SPI flash controller (SPIFC), after Linux
+// drivers/spi/spi-meson-spifc.c
+//
+// The controller shifts out and in up to 64 bytes per transfer through a
+// buffer of registers, keeping chip select active between transfers if told
+// so. Everything runs as register accesses from the host, which is slow but
+// needs nothing on the board.
+//
+// Writes go faster through a helper in SRAM, helpers/spi_flash.S, doing the
+// same per chunk: the host only loads the data and polls a status word. This
+// relies on the ROM acknowledging the run request and getting control back
+// when the helper returns; `--no-helper` falls back to register accesses.
+
+const REG_CMD: u32 = 0x00;
+const REG_CTRL: u32 = 0x08;
+const REG_CLOCK: u32 = 0x18;
+const REG_USER: u32 = 0x1c;
+const REG_USER1: u32 = 0x20;
+const REG_USER4: u32 = 0x2c;
+const REG_SLAVE: u32 = 0x30;
+const REG_C0: u32 = 0x40;
+
+const CMD_USER: u32 = 1 << 18;
+const CTRL_ENABLE_AHB: u32 = 1 << 17;
+const CLOCK_DIV_SHIFT: u32 = 12;
+const CLOCK_CNT_HIGH_SHIFT: u32 = 6;
+const CLOCK_CNT_LOW_SHIFT: u32 = 0;
+const USER_DIN_EN_MS: u32 = 1 << 0;
+const USER_CMP_MODE: u32 = 1 << 2;
+const USER_UC_DOUT_SEL: u32 = 1 << 27;
+const USER_UC_MASK: u32 = 0x1f << 27;
+const USER1_BN_UC_DOUT_SHIFT: u32 = 17;
+const USER4_CS_ACT: u32 = 1 << 30;
+const SLAVE_TRST_DONE: u32 = 1 << 4;
+const SLAVE_OP_MODE: u32 = 1 << 30;
+const SLAVE_SW_RST: u32 = 1 << 31;
+
+const BUFFER_SIZE: usize = 64;
+
+const GX_SPIFC: u32 = 0xc110_8c80;
+const G12_SPIFC: u32 = 0xffd1_4000;
+// HHI_GCLK_MPEG0, bit 30 is SPI
+const GX_GATE: u32 = 0xc883_c140;
+const G12_GATE: u32 = 0xff63_c140;
+const GATE_SPI: u32 = 1 << 30;
+
+// clk81 divided down to about 10 MHz
+const SPEED: u32 = 10_000_000;
+
+// SPI NOR commands
+const CMD_WREN: u8 = 0x06;
+const CMD_RDSR: u8 = 0x05;
+const CMD_READ: u8 = 0x03;
+const CMD_PP: u8 = 0x02;
+const CMD_SE: u8 = 0x20;
+const CMD_RDID: u8 = 0x9f;
+const CMD_RDSFDP: u8 = 0x5a;
+
+const SR_WIP: u8 = 1 << 0;
+
+pub const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: u32 = 256;
+
+const ERASE_TIMEOUT: Duration = Duration::from_secs(2);
+const PROGRAM_TIMEOUT: Duration = Duration::from_millis(100);
+
+// 3 byte addressing
+const FLASH_LIMIT: u64 = 1 << 24;
+
+// Where the helper runs, the BL2 area
+const GX_SRAM: u32 = 0xd900_0000;
+const G12_SRAM: u32 = 0xfffa_0000;
+
+const HELPER: &[u8] = include_bytes!("../helpers/spi_flash.bin");
+// offsets in SRAM
+const HELPER_PARAMS: u32 = 0x400;
+const HELPER_STATUS: u32 = HELPER_PARAMS + 0x0c;
+const HELPER_DETAIL: u32 = HELPER_PARAMS + 0x10;
+const HELPER_DATA: u32 = 0x1000;
+const HELPER_CHUNK: usize = 16 * 1024;
+// erasing and programming 4 sectors, plus USB timeouts while it runs
+const HELPER_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub struct Spifc<'a> {
+    h: &'a Handle,
+    t: Duration,
+    base: u32,
+    sram: u32,
+}
+
+impl<'a> Spifc<'a> {
+    /// Ungate the clock, mux the pins, reset the controller to master mode and
+    /// set the clock.
+    pub fn init(h: &'a Handle, t: Duration, soc: Soc) -> Result<Self, String> {
+        let (base, gate, sram) = match soc {
+            Soc::Gxbb => return Err("No NOR pinmux for GXBB yet".to_string()),
+            Soc::Gxl | Soc::Gxm => (GX_SPIFC, GX_GATE, GX_SRAM),
+            Soc::G12a | Soc::G12b | Soc::Sm1 => (G12_SPIFC, G12_GATE, G12_SRAM),
+        };
+        protocol::modify_reg(h, t, gate, 0, GATE_SPI, 0)?;
+        let pins = match soc.is_g12() {
+            true => ["BOOT_4", "BOOT_5", "BOOT_6", "BOOT_14"],
+            false => ["BOOT_11", "BOOT_12", "BOOT_13", "BOOT_15"],
+        };
+        for (pin, function) in pins.iter().zip(["nor_d", "nor_q", "nor_c", "nor_cs"]) {
+            pinmux::set(h, t, soc, pin, function)?;
+        }
+
+        let s = Spifc { h, t, base, sram };
+        s.modify(REG_SLAVE, 0, SLAVE_SW_RST)?;
+        // disable compatible mode, master mode
+        s.modify(REG_USER, USER_CMP_MODE, 0)?;
+        s.modify(REG_SLAVE, SLAVE_OP_MODE, 0)?;
+        s.modify(REG_CTRL, CTRL_ENABLE_AHB, 0)?;
+        let clk81 = clocks::clk81(h, t, soc)? as u32;
+        if clk81 == 0 {
+            return Err("clk81 is not running".to_string());
+        }
+        let div = clk81.div_ceil(SPEED).clamp(2, 0x40);
+        let clock = ((div - 1) << CLOCK_DIV_SHIFT)
+            | ((div / 2 - 1) << CLOCK_CNT_HIGH_SHIFT)
+            | ((div - 1) << CLOCK_CNT_LOW_SHIFT);
+        protocol::write_reg(h, t, base + REG_CLOCK, clock)?;
+        Ok(s)
+    }
+
+    fn modify(&self, reg: u32, clear: u32, set: u32) -> Result<(), String> {
+        protocol::modify_reg(self.h, self.t, self.base + reg, clear, set, 0)?;
+        Ok(())
+    }
+
+    /// Shift out `tx` and return what came in meanwhile. Chip select stays
+    /// active afterwards unless this is the last transfer of a command.
+    fn txrx(&self, tx: &[u8], last: bool) -> Result<Vec<u8>, String> {
+        let (h, t) = (self.h, self.t);
+        if tx.is_empty() || tx.len() > BUFFER_SIZE {
+            return Err(format!("SPIFC transfer of {} bytes", tx.len()));
+        }
+        // write_mem swaps each word, so swap back to get the bytes in order.
+        let mut buf = tx.to_vec();
+        buf.resize(tx.len().next_multiple_of(4), 0);
+        let swapped: Vec<u8> = buf
+            .chunks(4)
+            .flat_map(|c| c.iter().rev())
+            .copied()
+            .collect();
+        protocol::write_mem(h, t, self.base + REG_C0, &swapped)?;
+
+        self.modify(REG_USER, USER_UC_MASK, USER_UC_DOUT_SEL | USER_DIN_EN_MS)?;
+        let bits = 8 * tx.len() as u32 - 1;
+        protocol::write_reg(h, t, self.base + REG_USER1, bits << USER1_BN_UC_DOUT_SHIFT)?;
+        match last {
+            true => self.modify(REG_USER4, USER4_CS_ACT, 0)?,
+            false => self.modify(REG_USER4, 0, USER4_CS_ACT)?,
+        }
+        self.modify(REG_SLAVE, SLAVE_TRST_DONE, 0)?;
+        self.modify(REG_CMD, 0, CMD_USER)?;
+
+        let start = Instant::now();
+        while protocol::read_reg(h, t, self.base + REG_SLAVE)? & SLAVE_TRST_DONE == 0 {
+            if start.elapsed() > Duration::from_millis(100) {
+                return Err("SPIFC transfer timed out".to_string());
+            }
+        }
+        let words = protocol::read_block(h, t, self.base + REG_C0)?;
+        let mut rx: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        rx.truncate(tx.len());
+        Ok(rx)
+    }
+
+    /// Send a command, then read `len` bytes.
+    fn command(&self, cmd: &[u8], len: usize) -> Result<Vec<u8>, String> {
+        if len == 0 {
+            self.txrx(cmd, true)?;
+            return Ok(Vec::new());
+        }
+        self.txrx(cmd, false)?;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let n = (len - data.len()).min(BUFFER_SIZE);
+            let last = data.len() + n == len;
+            data.extend(self.txrx(&vec![0; n], last)?);
+        }
+        Ok(data)
+    }
+
+    fn wait_ready(&self, timeout: Duration) -> Result<(), String> {
+        let start = Instant::now();
+        while self.command(&[CMD_RDSR], 1)?[0] & SR_WIP != 0 {
+            if start.elapsed() > timeout {
+                return Err("Flash busy, timed out".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn jedec_id(&self) -> Result<[u8; 3], String> {
+        let id = self.command(&[CMD_RDID], 3)?;
+        Ok([id[0], id[1], id[2]])
+    }
+
+    pub fn sfdp(&self, addr: u32, len: usize) -> Result<Vec<u8>, String> {
+        let [_, a2, a1, a0] = addr.to_be_bytes();
+        // one dummy byte after the address
+        self.command(&[CMD_RDSFDP, a2, a1, a0, 0], len)
+    }
+
+    pub fn read(&self, addr: u32, len: usize) -> Result<Vec<u8>, String> {
+        check_range(addr, len)?;
+        let [_, a2, a1, a0] = addr.to_be_bytes();
+        self.command(&[CMD_READ, a2, a1, a0], len)
+    }
+
+    /// Erase the 4K sectors covering the range.
+    pub fn erase(&self, addr: u32, len: u32) -> Result<(), String> {
+        check_aligned(addr)?;
+        check_range(addr, len as usize)?;
+        for a in (addr..addr + len).step_by(SECTOR_SIZE as usize) {
+            let [_, a2, a1, a0] = a.to_be_bytes();
+            self.command(&[CMD_WREN], 0)?;
+            self.command(&[CMD_SE, a2, a1, a0], 0)?;
+            self.wait_ready(ERASE_TIMEOUT)?;
+            println!("  erased {a:08x}");
+        }
+        Ok(())
+    }
+
+    /// Program data, page by page; the range must be erased.
+    pub fn program(&self, addr: u32, data: &[u8]) -> Result<(), String> {
+        let mut a = addr;
+        let mut rest = data;
+        while !rest.is_empty() {
+            // pages must not be crossed
+            let n = ((PAGE_SIZE - a % PAGE_SIZE) as usize).min(rest.len());
+            let (page, next) = rest.split_at(n);
+            let [_, a2, a1, a0] = a.to_be_bytes();
+            self.command(&[CMD_WREN], 0)?;
+            self.txrx(&[CMD_PP, a2, a1, a0], false)?;
+            let chunks: Vec<&[u8]> = page.chunks(BUFFER_SIZE).collect();
+            for (i, c) in chunks.iter().enumerate() {
+                self.txrx(c, i == chunks.len() - 1)?;
+            }
+            self.wait_ready(PROGRAM_TIMEOUT)?;
+            a += n as u32;
+            rest = next;
+        }
+        Ok(())
+    }
+}
+
+/// Print the SFDP header, parameter headers and the flash size.
+pub fn print_sfdp(s: &Spifc) -> Result<(), String> {
+    let hdr = s.sfdp(0, 8)?;
+    if &hdr[0..4] != b"SFDP" {
+        return Err(format!("No SFDP signature: {:02x?}", &hdr[0..4]));
+    }
+    let nph = hdr[6] as usize + 1;
+    println!("SFDP {}.{}, {nph} parameter header(s)", hdr[5], hdr[4]);
+    let phs = s.sfdp(8, 8 * nph)?;
+    for ph in phs.chunks(8) {
+        let id = ((ph[7] as u16) << 8) | ph[0] as u16;
+        let ptr = u32::from_le_bytes([ph[4], ph[5], ph[6], 0]);
+        let dwords = ph[3] as usize;
+        println!(
+            "  table {id:04x} v{}.{} @{ptr:06x}, {dwords} dwords",
+            ph[2], ph[1]
+        );
+        // basic flash parameter table
+        if id == 0xff00 && dwords >= 2 {
+            let t = s.sfdp(ptr, 8)?;
+            let density = u32::from_le_bytes([t[4], t[5], t[6], t[7]]);
+            let bits: u64 = match density & (1 << 31) {
+                0 => density as u64 + 1,
+                _ => 1u64
+                    .checked_shl(density & 0x7fff_ffff)
+                    .ok_or(format!("Bad SFDP density {density:08x}"))?,
+            };
+            let erase_4k = t[0] & 0x3 == 0x1;
+            println!("    size {} KiB, 4K erase {erase_4k}", bits / 8 / 1024);
+        }
+    }
+    Ok(())
+}
+
+fn check_aligned(addr: u32) -> Result<(), String> {
+    match addr.is_multiple_of(SECTOR_SIZE) {
+        true => Ok(()),
+        false => Err(format!("Address {addr:08x} is not sector aligned")),
+    }
+}
+
+fn check_range(addr: u32, len: usize) -> Result<(), String> {
+    if addr as u64 + len as u64 > FLASH_LIMIT {
+        return Err(format!("Range {addr:08x}+{len:x} is beyond 16 MiB"));
+    }
+    Ok(())
+}
+
+/// Erase, program and read back.
+pub fn flash(s: &Spifc, addr: u32, data: &[u8]) -> Result<(), String> {
+    check_aligned(addr)?;
+    check_range(addr, data.len())?;
+    let len = data.len() as u32;
+    println!("Erase {len} bytes @{addr:08x}");
+    s.erase(addr, len.next_multiple_of(SECTOR_SIZE))?;
+    println!("Program");
+    s.program(addr, data)?;
+    println!("Verify");
+    let back = s.read(addr, data.len())?;
+    match back.iter().zip(data).position(|(a, b)| a != b) {
+        Some(i) => Err(format!("Mismatch at {:08x}", addr + i as u32)),
+        None => {
+            println!("OK");
+            Ok(())
+        }
+    }
+}
+
+fn wait_helper(s: &Spifc) -> Result<(u32, u32), String> {
+    let start = Instant::now();
+    loop {
+        // The ROM does not answer while the helper runs.
+        crate::journal::check()?;
+        if let Ok(status) = protocol::read_reg(s.h, s.t, s.sram + HELPER_STATUS) {
+            if status != 0 {
+                let detail = protocol::read_reg(s.h, s.t, s.sram + HELPER_DETAIL)?;
+                return Ok((status, detail));
+            }
+        }
+        if start.elapsed() > HELPER_TIMEOUT {
+            return Err("SPI flash helper did not finish".to_string());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Erase, program and read back with the SRAM helper, a chunk at a time.
+pub fn flash_helper(s: &Spifc, addr: u32, data: &[u8]) -> Result<(), String> {
+    check_aligned(addr)?;
+    check_range(addr, data.len())?;
+    let (h, t) = (s.h, s.t);
+    let mut helper = HELPER.to_vec();
+    helper.resize(HELPER.len().next_multiple_of(64), 0);
+    println!("Load helper @{:08x}", s.sram);
+    protocol::write(h, t, &helper, s.sram)?;
+    for (i, chunk) in data.chunks(HELPER_CHUNK).enumerate() {
+        let a = addr + (i * HELPER_CHUNK) as u32;
+        println!("Flash {} bytes @{a:08x}", chunk.len());
+        let mut buf = chunk.to_vec();
+        buf.resize(chunk.len().next_multiple_of(64), 0xff);
+        protocol::write(h, t, &buf, s.sram + HELPER_DATA)?;
+        // base, address, length, status, detail
+        let params: Vec<u8> = [s.base, a, chunk.len() as u32, 0, 0]
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect();
+        protocol::write_mem(h, t, s.sram + HELPER_PARAMS, &params)?;
+        protocol::exec(h, t, s.sram)?;
+        match wait_helper(s)? {
+            (1, _) => {}
+            (2, _) => return Err("SPIFC transfer timed out".to_string()),
+            (3, _) => return Err("Flash busy, timed out".to_string()),
+            (4, offs) => return Err(format!("Mismatch at {:08x}", a + offs)),
+            (status, _) => return Err(format!("SPI flash helper status {status}")),
+        }
+    }
+    println!("OK");
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use crate::protocol::{self, Handle};
+use crate::soc::Soc;
+use std::time::Duration;
+
+// Watchdog, after Linux drivers/watchdog/meson_gxbb_wdt.c
+//
+// It counts ticks of the crystal divided down to 1 kHz and resets the whole
+// SoC when the count reaches the timeout.
+
+const CTRL: u32 = 0x0;
+const TCNT: u32 = 0x8;
+const RSET: u32 = 0xc;
+
+const CTRL_CLKDIV_EN: u32 = 1 << 25;
+const CTRL_CLK_EN: u32 = 1 << 24;
+const CTRL_EE_RESET: u32 = 1 << 21;
+const CTRL_EN: u32 = 1 << 18;
+const CTRL_DIV_MASK: u32 = (1 << 18) - 1;
+const TCNT_SETUP_MASK: u32 = (1 << 16) - 1;
+const TCNT_CNT_SHIFT: u32 = 16;
+
+const XTAL: u32 = 24_000_000;
+
+fn base(soc: Soc) -> u32 {
+    match soc.is_g12() {
+        false => 0xc110_98d0,
+        true => 0xffd0_f0d0,
+    }
+}
+
+/// Start the watchdog with a timeout in milliseconds.
+pub fn arm(h: &Handle, t: Duration, soc: Soc, ms: u32) -> Result<(), String> {
+    if ms == 0 || ms > TCNT_SETUP_MASK {
+        return Err(format!("Timeout must be 1 to {TCNT_SETUP_MASK} ms"));
+    }
+    let base = base(soc);
+    let ctrl = CTRL_EE_RESET | CTRL_CLK_EN | CTRL_CLKDIV_EN | ((XTAL / 1000 - 1) & CTRL_DIV_MASK);
+    protocol::write_reg(h, t, base + CTRL, ctrl)?;
+    protocol::write_reg(h, t, base + TCNT, ms)?;
+    protocol::write_reg(h, t, base + RSET, 0)?;
+    protocol::write_reg(h, t, base + CTRL, ctrl | CTRL_EN)
+}
+
+/// Stop the watchdog, e.g. so that the ROM does not time out in long sessions.
+pub fn disable(h: &Handle, t: Duration, soc: Soc) -> Result<(), String> {
+    let base = base(soc);
+    protocol::modify_reg(h, t, base + CTRL, CTRL_EN, 0, 0)?;
+    protocol::write_reg(h, t, base + RSET, 0)
+}
+
+/// Print whether the watchdog runs, its timeout and current count.
+pub fn status(h: &Handle, t: Duration, soc: Soc) -> Result<(), String> {
+    let base = base(soc);
+    let ctrl = protocol::read_reg(h, t, base + CTRL)?;
+    let tcnt = protocol::read_reg(h, t, base + TCNT)?;
+    let state = if ctrl & CTRL_EN != 0 {
+        "running"
+    } else {
+        "stopped"
+    };
+    let div = (ctrl & CTRL_DIV_MASK) + 1;
+    let timeout = tcnt & TCNT_SETUP_MASK;
+    let count = tcnt >> TCNT_CNT_SHIFT;
+    let tick = div / (XTAL / 1_000_000);
+    println!("Watchdog {state}, at {count} of {timeout} ticks of {tick} us");
+    Ok(())
+}
+
+/// Reset the SoC by letting the watchdog run out right away.
+pub fn reset(h: &Handle, t: Duration, soc: Soc) -> Result<(), String> {
+    println!("Resetting...");
+    arm(h, t, soc, 1)
+}